@@ -0,0 +1,39 @@
+//! Generates the `KPF*` kpageflags layouts and the canonical layout from the table in
+//! `src/kpageflags/table.rs`.
+
+use std::{env, fs, path::Path};
+
+#[path = "src/kpageflags/table.rs"]
+mod table;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/kpageflags/table.rs");
+
+    let mut out = String::new();
+    for &version in table::LAYOUTS {
+        let (major, minor, patch) = version;
+        let flags: Vec<_> = table::FLAGS
+            .iter()
+            .filter(|flag| flag.in_layout(version))
+            .map(|flag| (flag.name, flag.bit))
+            .collect();
+
+        let block = table::kpf_block(&format!("KPF{major}_{minor}_{patch}"), &flags)
+            .unwrap_or_else(|err| panic!("{err}"));
+        out += &format!("// kpageflags for kernel {major}.{minor}.{patch}\n{block}\n");
+    }
+
+    let canonical = table::canonical_flags();
+    let block = table::kpf_block("Canonical", &canonical).unwrap_or_else(|err| panic!("{err}"));
+    out += &format!("// The flags of every kernel, each at one bit\n{block}\n");
+
+    out += "canonical_flag! {\n";
+    for (name, bit) in canonical.iter() {
+        out += &format!("    {name} = {bit},\n");
+    }
+    out += "}\n";
+
+    let path = Path::new(&env::var("OUT_DIR").unwrap()).join("kpf.rs");
+    fs::write(path, out).unwrap();
+}
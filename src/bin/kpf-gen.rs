@@ -0,0 +1,108 @@
+//! Generates the `table.rs` edits for a kernel's `KPF_*` defines. See `--help` for usage.
+
+use std::{fs::File, io::BufReader, process::exit};
+
+use encyclopagia::{
+    kernel::KernelVersion,
+    kpageflags::{merge_kpf_defines, parse_kpf_defines, TableEdits},
+};
+
+const USAGE: &str = "\
+kpf-gen [options] VERSION FILE...
+
+Reads the KPF_* defines of kernel VERSION (e.g., 6.18.0) from its sources, and prints the edits
+to src/kpageflags/table.rs that give it a matching layout. Pass all of:
+  include/uapi/linux/kernel-page-flags.h
+  include/linux/kernel-page-flags.h      (or fs/proc/page.c on older kernels)
+  tools/mm/page-types.c                  (tools/vm/page-types.c before 6.3)
+
+Options:
+  -c, --check    Exit with status 1 if the table needs any edits
+  -h, --help     Show this message
+";
+
+struct Options {
+    check: bool,
+    version: KernelVersion,
+    files: Vec<String>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut check = false;
+    let mut positional = Vec::new();
+
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "-c" | "--check" => check = true,
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                exit(0);
+            }
+            other if !other.starts_with('-') => positional.push(arg),
+            other => return Err(format!("unknown option: {}", other)),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let version = positional
+        .next()
+        .ok_or("no kernel version")?
+        .parse()
+        .map_err(|err| format!("{}", err))?;
+    let files: Vec<_> = positional.collect();
+    if files.is_empty() {
+        return Err("no input files".into());
+    }
+
+    Ok(Options {
+        check,
+        version,
+        files,
+    })
+}
+
+fn main() {
+    let opts = match parse_args() {
+        Ok(opts) => opts,
+        Err(err) => {
+            eprintln!("kpf-gen: {}\n\n{}", err, USAGE);
+            exit(2);
+        }
+    };
+
+    let mut files = Vec::new();
+    for path in opts.files.iter() {
+        match File::open(path).and_then(|file| parse_kpf_defines(BufReader::new(file))) {
+            Ok(defines) => files.push(defines),
+            Err(err) => {
+                eprintln!("kpf-gen: {}: {}", path, err);
+                exit(1);
+            }
+        }
+    }
+
+    let defines = match merge_kpf_defines(files) {
+        Ok(defines) => defines,
+        Err(err) => {
+            eprintln!("kpf-gen: {}", err);
+            exit(1);
+        }
+    };
+
+    let edits = match TableEdits::new(opts.version, &defines) {
+        Ok(edits) => edits,
+        Err(err) => {
+            eprintln!("kpf-gen: {}", err);
+            exit(1);
+        }
+    };
+
+    if edits.is_empty() {
+        println!("// table.rs already matches kernel {}", opts.version);
+    } else {
+        print!("{}", edits);
+        if opts.check {
+            exit(1);
+        }
+    }
+}
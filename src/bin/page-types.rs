@@ -0,0 +1,782 @@
+//! A `page-types`-like tool built on this crate. See `--help` for usage.
+
+use std::{
+    cell::RefCell,
+    error::Error,
+    fs::File,
+    io::{self, Read},
+    ops::Range,
+    process::exit,
+};
+
+use encyclopagia::{
+    kernel::{KernelError, KernelLayout, KernelVersion, LayoutVisitor},
+    kpageflags::{
+        changed_regions, FlagHistogram, Flaggy, KPageFlags, KPageFlagsDiff, KPageFlagsFile,
+        KPageFlagsIterator, KPageFlagsReader, TransitionMatrix, KPAGEFLAGS_PATH,
+    },
+    maps::read_maps,
+    pagemap::{PageMapFile, PageMapPage, PageMappy},
+    resolve::PageResolver,
+    snapshot::{Snapshot, SNAPSHOT_MAGIC},
+    Error as ReadError,
+};
+
+const USAGE: &str = "\
+page-types [options]
+page-types diff [-l|-L] [-N] [-r] [-H] OLD NEW
+
+Compares two captures, each either a snapshot or a raw dump of /proc/kpageflags.
+
+Options:
+  -p, --pid PID          Walk the address space of process PID
+  -a, --addr ADDR-SPEC   Walk a range of pages (may be repeated)
+  -b, --bits BITS-SPEC   Walk pages with the specified bits (may be repeated)
+  -l, --list             Show page details in ranges
+  -L, --list-each        Show page details one by one
+  -N, --no-summary       Don't show the summary info
+  -r, --raw              Don't hide kernel-internal flags
+  -H, --hex              Show flags as hex only, without names
+  -h, --help             Show this message
+
+ADDR-SPEC (in pages; virtual pages with -p):
+  N                      one page at offset N
+  N+M                    pages from N to N+M-1
+  N,M                    pages from N to M-1
+  N,                     pages from N to the end
+  ,M                     pages from 0 to M-1
+
+BITS-SPEC (flag names as printed, e.g., Lru,Anon):
+  bit1,bit2              (flags & (bit1|bit2)) != 0
+  bit1,~bit2             (flags & (bit1|bit2)) == bit1
+  =bit1,bit2             flags == (bit1|bit2)
+";
+
+/// The page-level details to print.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum List {
+    None,
+    Ranges,
+    Each,
+}
+
+struct Options {
+    pid: Option<u32>,
+    ranges: Vec<Range<u64>>,
+    bits: Vec<String>,
+    list: List,
+    summary: bool,
+    raw: bool,
+    hex: bool,
+    /// The captures to compare, for the `diff` subcommand.
+    diff: Option<(String, String)>,
+}
+
+fn parse_num(s: &str) -> Result<u64, String> {
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("invalid number: {}", s))
+}
+
+fn parse_addr_spec(spec: &str) -> Result<Range<u64>, String> {
+    if let Some((start, len)) = spec.split_once('+') {
+        let start = parse_num(start)?;
+        let end = start
+            .checked_add(parse_num(len)?)
+            .ok_or_else(|| format!("range overflows: {}", spec))?;
+        Ok(start..end)
+    } else if let Some((start, end)) = spec.split_once(',') {
+        let start = if start.is_empty() {
+            0
+        } else {
+            parse_num(start)?
+        };
+        let end = if end.is_empty() {
+            u64::MAX
+        } else {
+            parse_num(end)?
+        };
+        Ok(start..end)
+    } else {
+        let start = parse_num(spec)?;
+        let end = start
+            .checked_add(1)
+            .ok_or_else(|| format!("range overflows: {}", spec))?;
+        Ok(start..end)
+    }
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut opts = Options {
+        pid: None,
+        ranges: Vec::new(),
+        bits: Vec::new(),
+        list: List::None,
+        summary: true,
+        raw: false,
+        hex: false,
+        diff: None,
+    };
+    let mut positional = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("{} requires a value", arg))
+        };
+
+        match arg.as_str() {
+            "-p" | "--pid" => {
+                let pid = value()?;
+                opts.pid = Some(pid.parse().map_err(|_| format!("invalid pid: {}", pid))?);
+            }
+            "-a" | "--addr" => opts.ranges.push(parse_addr_spec(&value()?)?),
+            "-b" | "--bits" => opts.bits.push(value()?),
+            "-l" | "--list" => opts.list = List::Ranges,
+            "-L" | "--list-each" => opts.list = List::Each,
+            "-N" | "--no-summary" => opts.summary = false,
+            "-r" | "--raw" => opts.raw = true,
+            "-H" | "--hex" => opts.hex = true,
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                exit(0);
+            }
+            other if !other.starts_with('-') => positional.push(arg),
+            other => return Err(format!("unknown option: {}", other)),
+        }
+    }
+
+    match positional.as_slice() {
+        [] => {}
+        [cmd, old, new] if cmd == "diff" => {
+            if opts.pid.is_some() || !opts.ranges.is_empty() || !opts.bits.is_empty() {
+                return Err("diff does not support -p, -a, or -b".into());
+            }
+            opts.diff = Some((old.clone(), new.clone()));
+        }
+        [cmd, ..] if cmd == "diff" => return Err("diff takes exactly two captures".into()),
+        [other, ..] => return Err(format!("unexpected argument: {}", other)),
+    }
+
+    if opts.ranges.is_empty() {
+        opts.ranges.push(0..u64::MAX);
+    }
+
+    Ok(opts)
+}
+
+/// A filter on flags, as given by one `--bits` argument.
+struct BitsFilter<K: Flaggy> {
+    mask: K,
+    value: K,
+    /// If `true`, match pages with any bit in `mask` set, ignoring `value`.
+    any: bool,
+}
+
+impl<K: Flaggy> BitsFilter<K> {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let (exact, spec) = match spec.strip_prefix('=') {
+            Some(spec) => (true, spec),
+            None => (false, spec),
+        };
+
+        let mut mask = K::empty();
+        let mut value = K::empty();
+        let mut negated = false;
+        for name in spec.split(',') {
+            let (name, set) = match name.strip_prefix('~') {
+                Some(name) => (name, false),
+                None => (name, true),
+            };
+            let flag = name
+                .parse::<K>()
+                .map_err(|_| format!("unknown flag: {}", name))?;
+
+            mask |= flag;
+            if set {
+                value |= flag;
+            } else {
+                negated = true;
+            }
+        }
+
+        Ok(BitsFilter {
+            mask: if exact { !K::empty() } else { mask },
+            value,
+            any: !exact && !negated,
+        })
+    }
+
+    /// Compares raw bits, since pages may have bits that `K` doesn't know, e.g., if we fell back
+    /// to the layout of an older kernel.
+    fn matches(&self, flags: KPageFlags<K>) -> bool {
+        let masked = flags.as_u64() & self.mask.into();
+        if self.any {
+            masked != 0
+        } else {
+            masked == self.value.into()
+        }
+    }
+}
+
+/// Renders flags on one line, with any bits that `K` doesn't know in hex at the end.
+fn symbolic<K: Flaggy>(flags: u64) -> String {
+    let valid: u64 = K::valid_mask().into();
+    let mut names: Vec<_> = KPageFlags::from(K::from(flags & valid))
+        .to_string()
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect();
+
+    if flags & !valid != 0 {
+        names.push(format!("0x{:x}", flags & !valid));
+    }
+
+    names.join(" ")
+}
+
+/// Prints pages, either one by one or coalesced into ranges.
+struct Lister {
+    list: List,
+    hex: bool,
+    /// The current range: (virtual page, PFN, number of pages, flags).
+    current: Option<(Option<u64>, u64, u64, u64)>,
+}
+
+impl Lister {
+    fn new(list: List, hex: bool, process: bool) -> Self {
+        match (list, process) {
+            (List::None, _) => {}
+            (List::Ranges, false) => println!("offset\tlen\tflags"),
+            (List::Ranges, true) => println!("voffset\toffset\tlen\tflags"),
+            (List::Each, false) => println!("offset\tflags"),
+            (List::Each, true) => println!("voffset\toffset\tflags"),
+        }
+
+        Lister {
+            list,
+            hex,
+            current: None,
+        }
+    }
+
+    fn show<K: Flaggy>(&self, vpage: Option<u64>, pfn: u64, npages: u64, flags: u64) {
+        let mut line = String::new();
+        if let Some(vpage) = vpage {
+            line.push_str(&format!("{:x}\t", vpage));
+        }
+        line.push_str(&format!("{:x}\t", pfn));
+        if self.list == List::Ranges {
+            line.push_str(&format!("{}\t", npages));
+        }
+        line.push_str(&format!("0x{:016x}", flags));
+        if !self.hex {
+            line.push_str(&format!("\t{}", symbolic::<K>(flags)));
+        }
+        println!("{}", line);
+    }
+
+    fn add<K: Flaggy>(&mut self, vpage: Option<u64>, pfn: u64, flags: KPageFlags<K>) {
+        let flags = flags.as_u64();
+
+        match (self.list, &mut self.current) {
+            (List::None, _) => {}
+            (List::Each, _) => self.show::<K>(vpage, pfn, 1, flags),
+            (List::Ranges, Some((cur_vpage, cur_pfn, npages, cur_flags)))
+                if *cur_flags == flags
+                    && pfn == *cur_pfn + *npages
+                    && vpage == cur_vpage.map(|v| v + *npages) =>
+            {
+                *npages += 1;
+            }
+            (List::Ranges, _) => {
+                self.flush::<K>();
+                self.current = Some((vpage, pfn, 1, flags));
+            }
+        }
+    }
+
+    fn flush<K: Flaggy>(&mut self) {
+        if let Some((vpage, pfn, npages, flags)) = self.current.take() {
+            self.show::<K>(vpage, pfn, npages, flags);
+        }
+    }
+}
+
+/// The flags meant only for kernel hackers, at bits 32-47, as `KPF_HACKERS_BITS` in `page-types`.
+const HACKERS_BITS: u64 = 0xffff << 32;
+
+/// Unless we want raw flags, hide the kernel-internal ones, like `page-types` does.
+fn hidden_flags<K: Flaggy>(raw: bool) -> K {
+    let mut hidden = K::empty();
+    if !raw {
+        for flag in K::values() {
+            if Into::<u64>::into(*flag) & HACKERS_BITS != 0 {
+                hidden |= *flag;
+            }
+        }
+    }
+    hidden
+}
+
+/// Adds the bits of a pagemap entry that `page-types` reports as flags (`File`, `Swap`, and
+/// `MmapExclusive`), if layout `K` has them.
+fn with_pagemap_flags<K: Flaggy, P: PageMappy>(
+    mut flags: KPageFlags<K>,
+    page: PageMapPage<P>,
+) -> KPageFlags<K> {
+    let bits = [
+        ("File", page.has(P::FILE_OR_SHM)),
+        ("Swap", page.has(P::SWAPPED)),
+        (
+            "MmapExclusive",
+            P::EXCLUSIVE.is_some_and(|flag| page.has(flag)),
+        ),
+    ];
+    for (name, set) in bits {
+        if let (true, Ok(flag)) = (set, name.parse::<K>()) {
+            flags |= KPageFlags::from(flag);
+        }
+    }
+    flags
+}
+
+/// The flags of each page of a capture, in order of PFN.
+type Records<'a, K> = Box<dyn Iterator<Item = Result<KPageFlags<K>, ReadError>> + 'a>;
+
+/// A capture to compare: either a snapshot or a raw (possibly compressed) dump of
+/// `/proc/kpageflags`.
+enum Input {
+    Snapshot(Snapshot),
+    Raw(String),
+}
+
+impl Input {
+    fn open(path: &str) -> io::Result<Self> {
+        let mut magic = [0; SNAPSHOT_MAGIC.len()];
+        let is_snapshot =
+            File::open(path)?.read_exact(&mut magic).is_ok() && magic == SNAPSHOT_MAGIC;
+
+        Ok(if is_snapshot {
+            Input::Snapshot(Snapshot::open(path)?)
+        } else {
+            Input::Raw(path.to_owned())
+        })
+    }
+
+    /// The kernel release the capture came from, if known.
+    fn release(&self) -> Option<&str> {
+        match self {
+            Input::Snapshot(snapshot) => Some(&snapshot.header().release),
+            Input::Raw(_) => None,
+        }
+    }
+
+    /// The PFN of the first page in the capture.
+    fn pfn_base(&self) -> u64 {
+        match self {
+            Input::Snapshot(snapshot) => snapshot.header().pfn_base,
+            Input::Raw(_) => 0,
+        }
+    }
+
+    /// The page size of the machine the capture came from.
+    fn page_size(&self) -> u64 {
+        match self {
+            Input::Snapshot(snapshot) => snapshot.header().page_size,
+            Input::Raw(_) => encyclopagia::page_size() as u64,
+        }
+    }
+
+    fn kpageflags<K: Flaggy>(&self) -> Result<Records<'_, K>, Box<dyn Error>> {
+        Ok(match self {
+            Input::Snapshot(snapshot) => Box::new(
+                snapshot
+                    .kpageflags(&[])
+                    .ok_or("snapshot has no kpageflags section")?,
+            ),
+            Input::Raw(path) => Box::new(KPageFlagsIterator::new(
+                KPageFlagsReader::open_path(path)?,
+                &[],
+            )),
+        })
+    }
+}
+
+/// Ends `iter` at its first error, which is kept in `error` to be reported once we are done.
+fn until_error<'a, T: 'a>(
+    iter: impl Iterator<Item = Result<T, ReadError>> + 'a,
+    error: &'a RefCell<Option<ReadError>>,
+) -> impl Iterator<Item = T> + 'a {
+    iter.map_while(move |item| match item {
+        Ok(item) => Some(item),
+        Err(err) => {
+            error.borrow_mut().get_or_insert(err);
+            None
+        }
+    })
+}
+
+/// The actual tool, for a particular kernel layout.
+struct PageTypes(Options, Option<(Input, Input)>);
+
+impl PageTypes {
+    fn walk<K: Flaggy, P: PageMappy>(
+        &self,
+        mut f: impl FnMut(Option<u64>, u64, KPageFlags<K>),
+    ) -> Result<(), Box<dyn Error>> {
+        let opts = &self.0;
+
+        let ignored = hidden_flags::<K>(opts.raw);
+
+        let filters = opts
+            .bits
+            .iter()
+            .map(|spec| BitsFilter::<K>::parse(spec))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut visit = |vpage: Option<u64>, pfn: u64, mut flags: KPageFlags<K>| {
+            flags.clear(ignored);
+            if filters.is_empty() || filters.iter().any(|filter| filter.matches(flags)) {
+                f(vpage, pfn, flags);
+            }
+        };
+
+        match opts.pid {
+            None => {
+                let kpageflags = KPageFlagsFile::<K>::open(KPAGEFLAGS_PATH)?;
+                for range in opts.ranges.iter() {
+                    let pages = kpageflags
+                        .iter_from(range.start)
+                        .take(range.end.saturating_sub(range.start) as usize);
+                    for (pfn, flags) in (range.start..).zip(pages) {
+                        visit(None, pfn, flags?);
+                    }
+                }
+            }
+
+            Some(pid) => {
+                let resolver = PageResolver::<P, K>::open(pid)?;
+                let page_size = PageMapFile::<P>::open(pid)?.page_size() as u64;
+                let mut unknown = 0;
+
+                for vma in read_maps(pid)? {
+                    let vma_pages = vma.start as u64 / page_size..vma.end as u64 / page_size;
+                    for range in opts.ranges.iter() {
+                        let start = range.start.max(vma_pages.start);
+                        let end = range.end.min(vma_pages.end);
+                        if start >= end {
+                            continue;
+                        }
+
+                        let vaddrs = (start * page_size) as usize..(end * page_size) as usize;
+                        for (vaddr, page, flags) in resolver.resolve_entries(vaddrs)? {
+                            let vpage = Some(vaddr as u64 / page_size);
+                            match (page.pfn(), flags) {
+                                (Some(pfn), Some(flags)) => {
+                                    visit(vpage, pfn, with_pagemap_flags(flags, page))
+                                }
+                                _ => unknown += 1,
+                            }
+                        }
+                    }
+                }
+
+                if unknown > 0 {
+                    eprintln!(
+                        "page-types: warning: skipped {} pages with PFNs past the end of {}",
+                        unknown, KPAGEFLAGS_PATH
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl PageTypes {
+    /// Compares two captures, for the `diff` subcommand.
+    fn diff<K: Flaggy>(&self) -> Result<(), Box<dyn Error>> {
+        let opts = &self.0;
+        let (old, new) = self.1.as_ref().unwrap();
+
+        if old.pfn_base() != new.pfn_base() {
+            return Err("the captures start at different PFNs".into());
+        }
+
+        let hidden = hidden_flags::<K>(opts.raw);
+        let hide = move |mut flags: KPageFlags<K>| {
+            flags.clear(hidden);
+            flags
+        };
+
+        // Stop at the first error in either capture, rather than comparing a truncated capture.
+        let old_error = RefCell::new(None);
+        let new_error = RefCell::new(None);
+
+        let mut matrix = TransitionMatrix::<K>::new().with_page_size(old.page_size());
+        let transitions = KPageFlagsDiff::new(
+            until_error(old.kpageflags::<K>()?, &old_error).map(hide),
+            until_error(new.kpageflags::<K>()?, &new_error).map(hide),
+            old.pfn_base(),
+        )
+        .inspect(|transition| matrix.add(*transition));
+
+        match opts.list {
+            List::None => transitions.for_each(drop),
+            List::Ranges => {
+                println!("offset\tlen");
+                for region in changed_regions(transitions) {
+                    println!("{:x}\t{}", region.start, region.end - region.start);
+                }
+            }
+            List::Each => {
+                println!("offset\tbefore\tafter");
+                for transition in transitions {
+                    let mut line = format!(
+                        "{:x}\t0x{:016x}\t0x{:016x}",
+                        transition.pfn,
+                        transition.before.as_u64(),
+                        transition.after.as_u64()
+                    );
+                    if !opts.hex {
+                        line.push_str(&format!("\t{}", transition));
+                    }
+                    println!("{}", line);
+                }
+            }
+        }
+
+        let (old_path, new_path) = opts.diff.as_ref().unwrap();
+        for (path, error) in [(old_path, old_error), (new_path, new_error)] {
+            if let Some(err) = error.into_inner() {
+                return Err(format!("{}: {}", path, err).into());
+            }
+        }
+
+        if opts.summary {
+            if opts.list != List::None {
+                println!();
+            }
+            print!("{}", matrix);
+        }
+
+        Ok(())
+    }
+}
+
+impl LayoutVisitor for PageTypes {
+    type Output = Result<(), Box<dyn Error>>;
+
+    fn visit<K: Flaggy, P: PageMappy>(self) -> Self::Output {
+        if self.1.is_some() {
+            return self.diff::<K>();
+        }
+
+        let opts = &self.0;
+        let mut lister = Lister::new(opts.list, opts.hex, opts.pid.is_some());
+        let mut histogram = FlagHistogram::<K>::new();
+
+        self.walk::<K, P>(|vpage, pfn, flags| {
+            lister.add(vpage, pfn, flags);
+            histogram.add(flags);
+        })?;
+        lister.flush::<K>();
+
+        if opts.summary {
+            if opts.list != List::None {
+                println!();
+            }
+            print!("{}", histogram);
+        }
+
+        Ok(())
+    }
+}
+
+fn main() {
+    let opts = match parse_args() {
+        Ok(opts) => opts,
+        Err(err) => {
+            eprintln!("page-types: {}\n\n{}", err, USAGE);
+            exit(2);
+        }
+    };
+
+    let inputs = match &opts.diff {
+        None => None,
+        Some((old, new)) => match Input::open(old).and_then(|old| Ok((old, Input::open(new)?))) {
+            Ok(inputs) => Some(inputs),
+            Err(err) => {
+                eprintln!("page-types: {}", err);
+                exit(1);
+            }
+        },
+    };
+
+    // Use the layout of the kernel the captures came from, if we know it.
+    let version = match inputs
+        .iter()
+        .flat_map(|(old, new)| [old, new])
+        .find_map(Input::release)
+    {
+        Some(release) => release.parse(),
+        None => KernelVersion::running(),
+    };
+
+    let layout = match version.and_then(KernelLayout::for_version) {
+        Ok(layout) => layout,
+        Err(err) => {
+            let version = match err {
+                KernelError::TooOld(version) | KernelError::TooNew(version) => version,
+                err => {
+                    eprintln!("page-types: {}", err);
+                    exit(1);
+                }
+            };
+            let layout = KernelLayout::nearest(version);
+            eprintln!(
+                "page-types: warning: {}; using the layout of {}",
+                err,
+                layout.version()
+            );
+            layout
+        }
+    };
+
+    if let Err(err) = layout.dispatch(PageTypes(opts, inputs)) {
+        eprintln!("page-types: {}", err);
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use encyclopagia::{
+        kpageflags::{KPF3_10_0, KPF6_0_0},
+        pagemap::{PageMapReader, PM3_10_0, PM6_0_0},
+    };
+
+    use super::*;
+
+    /// Casts raw bits to flags, the way reading them from a file does, even if `K` doesn't know
+    /// some of them.
+    fn raw_flags<K: Flaggy>(bits: u64) -> KPageFlags<K> {
+        let bytes = bits.to_ne_bytes();
+        let reader = KPageFlagsReader::<_, K>::new(BufReader::new(&bytes[..]));
+        KPageFlagsIterator::new(reader, &[])
+            .next()
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn addr_specs() {
+        assert_eq!(parse_addr_spec("0x10"), Ok(0x10..0x11));
+        assert_eq!(parse_addr_spec("5+3"), Ok(5..8));
+        assert_eq!(parse_addr_spec("5,8"), Ok(5..8));
+        assert_eq!(parse_addr_spec(",8"), Ok(0..8));
+        assert_eq!(parse_addr_spec("5,"), Ok(5..u64::MAX));
+        assert!(parse_addr_spec("1+0xffffffffffffffff").is_err());
+        assert!(parse_addr_spec("0xffffffffffffffff").is_err());
+    }
+
+    #[test]
+    fn bits_filters() {
+        let filter = BitsFilter::<KPF6_0_0::Flags>::parse("Lru,~Active").unwrap();
+        assert!(filter.matches(KPF6_0_0::Lru.into()));
+        assert!(!filter.matches((KPF6_0_0::Lru | KPF6_0_0::Active).into()));
+
+        let filter = BitsFilter::<KPF6_0_0::Flags>::parse("=Buddy").unwrap();
+        assert!(filter.matches(KPF6_0_0::Buddy.into()));
+        assert!(!filter.matches((KPF6_0_0::Buddy | KPF6_0_0::Idle).into()));
+    }
+
+    #[test]
+    fn unknown_bits_do_not_panic() {
+        // Bit 26 (`Pgtable`) is unknown to the 3.10 layout.
+        let flags = raw_flags::<KPF3_10_0::Flags>(u64::from(KPF3_10_0::Lru) | 1 << 26);
+
+        let filter = BitsFilter::<KPF3_10_0::Flags>::parse("Lru").unwrap();
+        assert!(filter.matches(flags));
+        let filter = BitsFilter::<KPF3_10_0::Flags>::parse("=Lru").unwrap();
+        assert!(!filter.matches(flags));
+
+        assert_eq!(
+            symbolic::<KPF3_10_0::Flags>(flags.as_u64()),
+            "Lru 0x4000000"
+        );
+    }
+
+    /// Casts raw bits to a pagemap entry, the way reading it from a file does.
+    fn raw_page<P: PageMappy>(bits: u64) -> PageMapPage<P> {
+        let bytes = bits.to_ne_bytes();
+        let mut page = [PageMapPage::empty()];
+        PageMapReader::<_, P>::new(BufReader::new(&bytes[..]))
+            .read(&mut page)
+            .unwrap();
+        page[0]
+    }
+
+    #[test]
+    fn only_kernel_internal_flags_are_hidden() {
+        let hidden = hidden_flags::<KPF6_0_0::Flags>(false);
+        assert_eq!(
+            format!("{:?}", hidden),
+            "Reserved Mlocked Mappedtodisk Private Private2 OwnerPrivate Arch Uncached \
+             Softdirty Arch2 AnonExclusive "
+        );
+        assert_eq!(
+            hidden_flags::<KPF6_0_0::Flags>(true),
+            KPF6_0_0::Flags::empty()
+        );
+    }
+
+    #[test]
+    fn pagemap_flags_are_reported() {
+        let present = 1 << u64::from(PM6_0_0::Present);
+        let file = 1 << u64::from(PM6_0_0::File);
+        let exclusive = 1 << u64::from(PM6_0_0::MmapExclusive);
+        let lru = KPageFlags::from(KPF6_0_0::Lru);
+
+        let flags = with_pagemap_flags(lru, raw_page::<PM6_0_0::Flags>(present | file | exclusive));
+        assert_eq!(
+            flags,
+            KPageFlags::from(KPF6_0_0::Lru | KPF6_0_0::File | KPF6_0_0::MmapExclusive)
+        );
+        assert_eq!(
+            with_pagemap_flags(lru, raw_page::<PM6_0_0::Flags>(present | 7)),
+            lru
+        );
+
+        // The 3.10 layouts have neither the flags nor exclusive mappings.
+        let lru = KPageFlags::from(KPF3_10_0::Lru);
+        let file = 1 << u64::from(PM3_10_0::File);
+        assert_eq!(
+            with_pagemap_flags(lru, raw_page::<PM3_10_0::Flags>(present | file)),
+            lru
+        );
+    }
+
+    #[test]
+    fn until_error_keeps_the_first_error() {
+        let error = RefCell::new(None);
+        let items = vec![
+            Ok(1),
+            Ok(2),
+            Err(ReadError::Compressed),
+            Ok(3),
+            Err(ReadError::Truncated { trailing_bytes: 1 }),
+        ];
+
+        let ok: Vec<_> = until_error(items.into_iter(), &error).collect();
+        assert_eq!(ok, [1, 2]);
+        assert!(matches!(error.into_inner(), Some(ReadError::Compressed)));
+    }
+}
@@ -0,0 +1,187 @@
+//! Transparent decompression of dumps of `/proc/kpageflags` and friends.
+//!
+//! Each format is behind a cargo feature: `gzip`, `zstd`, and `xz`. Without the matching feature,
+//! compressed input is reported as `Error::Compressed` instead.
+
+use std::io::{self, BufRead, BufReader, Read};
+
+use crate::Error;
+
+/// The compression formats we can recognize.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+impl Compression {
+    /// Returns the format whose magic bytes `buf` starts with, if any.
+    pub fn detect(buf: &[u8]) -> Option<Self> {
+        const MAGICS: &[(&[u8], Compression)] = &[
+            (&[0x1f, 0x8b], Compression::Gzip),
+            (&[0x28, 0xb5, 0x2f, 0xfd], Compression::Zstd),
+            (&[0xfd, b'7', b'z', b'X', b'Z', 0x00], Compression::Xz),
+            (b"BZh", Compression::Bzip2),
+        ];
+
+        MAGICS
+            .iter()
+            .find(|(magic, _)| buf.starts_with(magic))
+            .map(|(_, compression)| *compression)
+    }
+
+    /// Returns `true` if this crate was built with support for the format.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Compression::Gzip => cfg!(feature = "gzip"),
+            Compression::Zstd => cfg!(feature = "zstd"),
+            Compression::Xz => cfg!(feature = "xz"),
+            Compression::Bzip2 => false,
+        }
+    }
+}
+
+/// Returns `true` if `buf` starts with the magic bytes of a common compression format.
+pub(crate) fn looks_compressed(buf: &[u8]) -> bool {
+    Compression::detect(buf).is_some()
+}
+
+/// Sniffs the start of `reader`, and wraps it in a decoder if it is compressed. Uncompressed input
+/// is passed through unchanged.
+///
+/// Returns `Error::Compressed` (wrapped in an `io::Error`) if the input is in a format that this
+/// crate was built without support for.
+pub fn decompress<R: Read + 'static>(reader: R) -> io::Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(reader);
+    let compression = Compression::detect(reader.fill_buf()?);
+
+    Ok(match compression {
+        None => Box::new(reader),
+
+        #[cfg(feature = "gzip")]
+        Some(Compression::Gzip) => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+
+        #[cfg(feature = "zstd")]
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::with_buffer(reader)?),
+
+        #[cfg(feature = "xz")]
+        Some(Compression::Xz) => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(reader)),
+
+        Some(_) => return Err(Error::Compressed.into()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::kpagecount::{KPageCountIterator, KPageCountReader};
+
+    /// Counts that are long enough to span several decoder buffers.
+    fn counts() -> Vec<u64> {
+        (0..100_000).map(|pfn| pfn % 7).collect()
+    }
+
+    fn bytes() -> Vec<u8> {
+        counts().into_iter().flat_map(u64::to_ne_bytes).collect()
+    }
+
+    /// Writes `data` to a temporary file, and reads it back with `open_path`.
+    fn read_back(data: &[u8]) -> Result<Vec<u64>, Error> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(data).unwrap();
+
+        KPageCountIterator::new(KPageCountReader::open_path(file.path())?)
+            .map(|count| count.map(|count| count.as_u64()))
+            .collect()
+    }
+
+    /// Checks that `data` can only be read if `compression` is supported.
+    fn check(compression: Compression, data: &[u8]) {
+        assert_eq!(Compression::detect(data), Some(compression));
+
+        match read_back(data) {
+            Ok(counts) => {
+                assert!(compression.is_supported());
+                assert_eq!(counts, self::counts());
+            }
+            Err(Error::Compressed) => assert!(!compression.is_supported()),
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn magics_are_detected() {
+        assert_eq!(
+            Compression::detect(&[0x1f, 0x8b, 8]),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd]),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(
+            Compression::detect(b"\xfd7zXZ\x00\x00"),
+            Some(Compression::Xz)
+        );
+        assert_eq!(Compression::detect(b"BZh91AY"), Some(Compression::Bzip2));
+        assert_eq!(Compression::detect(&[0x1f]), None);
+        assert_eq!(Compression::detect(&[]), None);
+    }
+
+    #[test]
+    fn uncompressed_input_is_passed_through() {
+        assert_eq!(read_back(&bytes()).unwrap(), counts());
+    }
+
+    #[test]
+    fn bzip2_is_unsupported() {
+        check(Compression::Bzip2, b"BZh91AY&SY");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_round_trips() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(&bytes()).unwrap();
+        check(Compression::Gzip, &encoder.finish().unwrap());
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn gzip_needs_its_feature() {
+        check(Compression::Gzip, &[0x1f, 0x8b, 8, 0, 0, 0, 0, 0]);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_round_trips() {
+        check(
+            Compression::Zstd,
+            &zstd::encode_all(bytes().as_slice(), 1).unwrap(),
+        );
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn zstd_needs_its_feature() {
+        check(Compression::Zstd, &[0x28, 0xb5, 0x2f, 0xfd, 0, 0, 0, 0]);
+    }
+
+    #[cfg(feature = "xz")]
+    #[test]
+    fn xz_round_trips() {
+        let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 1);
+        encoder.write_all(&bytes()).unwrap();
+        check(Compression::Xz, &encoder.finish().unwrap());
+    }
+
+    #[cfg(not(feature = "xz"))]
+    #[test]
+    fn xz_needs_its_feature() {
+        check(Compression::Xz, b"\xfd7zXZ\x00\x00\x00");
+    }
+}
@@ -0,0 +1,103 @@
+//! The error type for reading files of `FileReadable` types.
+
+use std::io;
+
+use crate::kernel::KernelError;
+
+/// Errors from reading `/proc/kpageflags` and friends (or dumps of them).
+#[derive(Debug)]
+pub enum Error {
+    /// Permission was denied. Most of these files can only be read with `CAP_SYS_ADMIN`.
+    PermissionDenied(io::Error),
+    /// The input ended in the middle of a record.
+    Truncated {
+        /// The number of bytes of the incomplete record at the end of the input.
+        trailing_bytes: usize,
+    },
+    /// The input looks like compressed data rather than raw records, and it was not (or could not
+    /// be) decompressed. See `compress::decompress`.
+    Compressed,
+    /// The input is not a valid snapshot, or was written by an incompatible machine or version.
+    InvalidSnapshot(String),
+    /// A runtime-defined flag layout is malformed. See `kpageflags::DynamicLayout`.
+    InvalidLayout(String),
+    /// The kernel version could not be detected, or has no known layout.
+    Kernel(KernelError),
+    /// Any other I/O error.
+    Io(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::PermissionDenied {
+            return Error::PermissionDenied(err);
+        }
+
+        // Readers return our errors wrapped in an `io::Error`, so unwrap them again.
+        if err.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            return *err.into_inner().unwrap().downcast::<Error>().unwrap();
+        }
+
+        Error::Io(err)
+    }
+}
+
+impl From<KernelError> for Error {
+    fn from(err: KernelError) -> Self {
+        Error::Kernel(err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::PermissionDenied(err) | Error::Io(err) => err,
+            err @ Error::Truncated { .. } => io::Error::new(io::ErrorKind::UnexpectedEof, err),
+            err @ (Error::Compressed | Error::InvalidSnapshot(_) | Error::InvalidLayout(_)) => {
+                io::Error::new(io::ErrorKind::InvalidData, err)
+            }
+            Error::Kernel(err) => {
+                let kind = match &err {
+                    KernelError::Io(err) => err.kind(),
+                    _ => io::ErrorKind::Unsupported,
+                };
+                io::Error::new(kind, Error::Kernel(err))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::PermissionDenied(err) => write!(f, "permission denied: {}", err),
+            Error::Truncated { trailing_bytes } => write!(
+                f,
+                "input ends with an incomplete record ({} trailing bytes)",
+                trailing_bytes
+            ),
+            Error::Compressed => write!(
+                f,
+                "input looks compressed; enable the cargo feature for its format and use \
+                 `open_path`, or decompress it first"
+            ),
+            Error::InvalidSnapshot(reason) => write!(f, "invalid snapshot: {}", reason),
+            Error::InvalidLayout(reason) => write!(f, "invalid layout: {}", reason),
+            Error::Kernel(err) => write!(f, "{}", err),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::PermissionDenied(err) | Error::Io(err) => Some(err),
+            Error::Kernel(err) => Some(err),
+            Error::Truncated { .. }
+            | Error::Compressed
+            | Error::InvalidSnapshot(_)
+            | Error::InvalidLayout(_) => None,
+        }
+    }
+}
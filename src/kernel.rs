@@ -0,0 +1,314 @@
+//! Tools for detecting the running kernel version and picking the matching `Flaggy` and
+//! `PageMappy` implementations at runtime.
+
+use std::{io, str::FromStr};
+
+use crate::{
+    kpageflags::{
+        Flaggy, KPF3_10_0, KPF4_15_0, KPF5_0_8, KPF5_13_0, KPF5_15_0, KPF5_17_0, KPF5_19_0,
+        KPF5_4_0, KPF6_0_0, KPF6_12_0, KPF6_1_0, KPF6_6_0,
+    },
+    pagemap::{
+        PageMappy, PM3_10_0, PM4_15_0, PM5_0_8, PM5_13_0, PM5_15_0, PM5_17_0, PM5_19_0, PM5_4_0,
+        PM6_0_0, PM6_12_0, PM6_1_0, PM6_6_0,
+    },
+};
+
+/// The file path... `/proc/sys/kernel/osrelease`.
+pub const OSRELEASE_PATH: &str = "/proc/sys/kernel/osrelease";
+
+/// A kernel version, e.g., `5.15.0`.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, PartialOrd, Ord)]
+pub struct KernelVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl KernelVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        KernelVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Reads the version of the running kernel.
+    pub fn running() -> Result<Self, KernelError> {
+        let release = std::fs::read_to_string(OSRELEASE_PATH)?;
+        release.trim().parse()
+    }
+}
+
+impl FromStr for KernelVersion {
+    type Err = KernelError;
+
+    /// Parses a release string, such as `5.15.0-91-generic` or `6.1.0`. Anything after the
+    /// numeric version is ignored, and a missing patch level is treated as 0.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let numeric = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .map_or(s, |end| &s[..end]);
+        let mut parts = numeric.split('.').map(str::parse::<u32>);
+
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(major)), Some(Ok(minor)), None) => Ok(KernelVersion::new(major, minor, 0)),
+            (Some(Ok(major)), Some(Ok(minor)), Some(Ok(patch))) => {
+                Ok(KernelVersion::new(major, minor, patch))
+            }
+            _ => Err(KernelError::Unparseable(s.to_owned())),
+        }
+    }
+}
+
+impl std::fmt::Display for KernelVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Errors from detecting the kernel version.
+#[derive(Debug)]
+pub enum KernelError {
+    /// Unable to read the kernel release.
+    Io(io::Error),
+    /// The kernel release could not be parsed.
+    Unparseable(String),
+    /// The kernel is older than any known layout.
+    TooOld(KernelVersion),
+    /// The kernel is newer than any known layout.
+    TooNew(KernelVersion),
+}
+
+impl From<io::Error> for KernelError {
+    fn from(err: io::Error) -> Self {
+        KernelError::Io(err)
+    }
+}
+
+impl std::fmt::Display for KernelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KernelError::Io(err) => write!(f, "unable to read kernel release: {}", err),
+            KernelError::Unparseable(release) => {
+                write!(f, "unable to parse kernel release: {}", release)
+            }
+            KernelError::TooOld(version) => write!(
+                f,
+                "kernel {} is older than the oldest known layout ({})",
+                version,
+                KernelLayout::ALL[0].version()
+            ),
+            KernelError::TooNew(version) => write!(
+                f,
+                "kernel {} is newer than the newest known layout ({})",
+                version,
+                KernelLayout::ALL[KernelLayout::ALL.len() - 1].version()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for KernelError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KernelError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Something to do with a particular pair of `Flaggy` and `PageMappy` implementations, chosen at
+/// runtime by `KernelLayout::dispatch`.
+pub trait LayoutVisitor {
+    type Output;
+
+    fn visit<K: Flaggy, P: PageMappy>(self) -> Self::Output;
+}
+
+/// The kernel versions for which we have `Flaggy` and `PageMappy` implementations.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, PartialOrd, Ord)]
+pub enum KernelLayout {
+    V3_10_0,
+    V4_15_0,
+    V5_0_8,
+    V5_4_0,
+    V5_13_0,
+    V5_15_0,
+    V5_17_0,
+    V5_19_0,
+    V6_0_0,
+    V6_1_0,
+    V6_6_0,
+    V6_12_0,
+}
+
+impl KernelLayout {
+    /// All known layouts, oldest first.
+    pub const ALL: &'static [KernelLayout] = &[
+        KernelLayout::V3_10_0,
+        KernelLayout::V4_15_0,
+        KernelLayout::V5_0_8,
+        KernelLayout::V5_4_0,
+        KernelLayout::V5_13_0,
+        KernelLayout::V5_15_0,
+        KernelLayout::V5_17_0,
+        KernelLayout::V5_19_0,
+        KernelLayout::V6_0_0,
+        KernelLayout::V6_1_0,
+        KernelLayout::V6_6_0,
+        KernelLayout::V6_12_0,
+    ];
+
+    /// The kernel version this layout was taken from.
+    pub const fn version(self) -> KernelVersion {
+        match self {
+            KernelLayout::V3_10_0 => KernelVersion::new(3, 10, 0),
+            KernelLayout::V4_15_0 => KernelVersion::new(4, 15, 0),
+            KernelLayout::V5_0_8 => KernelVersion::new(5, 0, 8),
+            KernelLayout::V5_4_0 => KernelVersion::new(5, 4, 0),
+            KernelLayout::V5_13_0 => KernelVersion::new(5, 13, 0),
+            KernelLayout::V5_15_0 => KernelVersion::new(5, 15, 0),
+            KernelLayout::V5_17_0 => KernelVersion::new(5, 17, 0),
+            KernelLayout::V5_19_0 => KernelVersion::new(5, 19, 0),
+            KernelLayout::V6_0_0 => KernelVersion::new(6, 0, 0),
+            KernelLayout::V6_1_0 => KernelVersion::new(6, 1, 0),
+            KernelLayout::V6_6_0 => KernelVersion::new(6, 6, 0),
+            KernelLayout::V6_12_0 => KernelVersion::new(6, 12, 0),
+        }
+    }
+
+    /// Returns the newest layout that is not newer than the given kernel, ignoring patch levels.
+    /// Kernels older or newer than all known layouts get the oldest or newest layout.
+    pub fn nearest(version: KernelVersion) -> Self {
+        let series = KernelVersion::new(version.major, version.minor, u32::MAX);
+        Self::ALL
+            .iter()
+            .rev()
+            .find(|layout| layout.version() <= series)
+            .copied()
+            .unwrap_or(Self::ALL[0])
+    }
+
+    /// Like `nearest`, but returns an error for kernels outside the range of known layouts.
+    pub fn for_version(version: KernelVersion) -> Result<Self, KernelError> {
+        let oldest = Self::ALL[0].version();
+        let newest = Self::ALL[Self::ALL.len() - 1].version();
+
+        if (version.major, version.minor) < (oldest.major, oldest.minor) {
+            Err(KernelError::TooOld(version))
+        } else if (version.major, version.minor) > (newest.major, newest.minor) {
+            Err(KernelError::TooNew(version))
+        } else {
+            Ok(Self::nearest(version))
+        }
+    }
+
+    /// Returns the layout for the running kernel.
+    pub fn detect() -> Result<Self, KernelError> {
+        Self::for_version(KernelVersion::running()?)
+    }
+
+    /// Calls `visitor` with the `Flaggy` and `PageMappy` implementations of this layout.
+    pub fn dispatch<V: LayoutVisitor>(self, visitor: V) -> V::Output {
+        match self {
+            KernelLayout::V3_10_0 => visitor.visit::<KPF3_10_0::Flags, PM3_10_0::Flags>(),
+            KernelLayout::V4_15_0 => visitor.visit::<KPF4_15_0::Flags, PM4_15_0::Flags>(),
+            KernelLayout::V5_0_8 => visitor.visit::<KPF5_0_8::Flags, PM5_0_8::Flags>(),
+            KernelLayout::V5_4_0 => visitor.visit::<KPF5_4_0::Flags, PM5_4_0::Flags>(),
+            KernelLayout::V5_13_0 => visitor.visit::<KPF5_13_0::Flags, PM5_13_0::Flags>(),
+            KernelLayout::V5_15_0 => visitor.visit::<KPF5_15_0::Flags, PM5_15_0::Flags>(),
+            KernelLayout::V5_17_0 => visitor.visit::<KPF5_17_0::Flags, PM5_17_0::Flags>(),
+            KernelLayout::V5_19_0 => visitor.visit::<KPF5_19_0::Flags, PM5_19_0::Flags>(),
+            KernelLayout::V6_0_0 => visitor.visit::<KPF6_0_0::Flags, PM6_0_0::Flags>(),
+            KernelLayout::V6_1_0 => visitor.visit::<KPF6_1_0::Flags, PM6_1_0::Flags>(),
+            KernelLayout::V6_6_0 => visitor.visit::<KPF6_6_0::Flags, PM6_6_0::Flags>(),
+            KernelLayout::V6_12_0 => visitor.visit::<KPF6_12_0::Flags, PM6_12_0::Flags>(),
+        }
+    }
+}
+
+/// Detects the running kernel and calls `visitor` with the matching `Flaggy` and `PageMappy`
+/// implementations.
+pub fn with_detected_kernel<V: LayoutVisitor>(visitor: V) -> Result<V::Output, KernelError> {
+    Ok(KernelLayout::detect()?.dispatch(visitor))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::*;
+
+    #[test]
+    fn versions_are_parsed() {
+        let parse = |s: &str| s.parse::<KernelVersion>().ok();
+
+        assert_eq!(
+            parse("5.15.0-91-generic"),
+            Some(KernelVersion::new(5, 15, 0))
+        );
+        assert_eq!(parse("6.1"), Some(KernelVersion::new(6, 1, 0)));
+        assert_eq!(parse("6.1.55+"), Some(KernelVersion::new(6, 1, 55)));
+        assert_eq!(
+            parse("4.18.0-513.el8.x86_64"),
+            Some(KernelVersion::new(4, 18, 0))
+        );
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("6"), None);
+        assert_eq!(parse("linux-6.1.0"), None);
+        assert!(matches!(
+            "garbage".parse::<KernelVersion>(),
+            Err(KernelError::Unparseable(release)) if release == "garbage"
+        ));
+    }
+
+    #[test]
+    fn nearest_layouts_are_found() {
+        let nearest =
+            |major, minor, patch| KernelLayout::nearest(KernelVersion::new(major, minor, patch));
+
+        assert_eq!(nearest(5, 15, 0), KernelLayout::V5_15_0);
+        assert_eq!(nearest(5, 15, 91), KernelLayout::V5_15_0);
+        assert_eq!(nearest(5, 16, 0), KernelLayout::V5_15_0);
+        assert_eq!(nearest(4, 15, 0), KernelLayout::V4_15_0);
+        assert_eq!(nearest(2, 6, 32), KernelLayout::V3_10_0);
+        // Patch levels are ignored, so 5.0.0 gets the layout of 5.0.8.
+        assert_eq!(nearest(5, 0, 0).version(), KernelVersion::new(5, 0, 8));
+    }
+
+    #[test]
+    fn layouts_are_limited_to_known_kernels() {
+        let oldest = KernelLayout::ALL[0];
+        let newest = KernelLayout::ALL[KernelLayout::ALL.len() - 1];
+        let for_version = |major, minor, patch| {
+            KernelLayout::for_version(KernelVersion::new(major, minor, patch))
+        };
+
+        let old = oldest.version();
+        assert_eq!(for_version(old.major, old.minor, 0).unwrap(), oldest);
+        assert!(matches!(
+            for_version(old.major, old.minor - 1, 0),
+            Err(KernelError::TooOld(_))
+        ));
+
+        let new = newest.version();
+        assert_eq!(for_version(new.major, new.minor, 999).unwrap(), newest);
+        assert!(matches!(
+            for_version(new.major, new.minor + 1, 0),
+            Err(KernelError::TooNew(version)) if version == KernelVersion::new(new.major, new.minor + 1, 0)
+        ));
+        assert!(matches!(
+            for_version(new.major + 1, 0, 0),
+            Err(KernelError::TooNew(_))
+        ));
+    }
+
+    #[test]
+    fn io_errors_are_the_source() {
+        let err = KernelError::from(io::Error::new(io::ErrorKind::NotFound, "gone"));
+        assert_eq!(err.source().unwrap().to_string(), "gone");
+        assert!(KernelError::Unparseable("x".into()).source().is_none());
+    }
+}
@@ -0,0 +1,320 @@
+//! Tools for reading `/proc/kpagecgroup` and attributing physical pages to memory cgroups.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    kpageflags::{Flaggy, KPageFlags},
+    FileReadable, FileReadableFile, FileReadableIterator, FileReadableReader,
+};
+
+/// The file path... `/proc/kpagecgroup`.
+pub const KPAGECGROUP_PATH: &str = "/proc/kpagecgroup";
+
+/// The default mount point of the cgroup v2 hierarchy.
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// The inode number of the memory cgroup that a single physical page frame is charged to, or 0 if
+/// it is not charged to any memory cgroup.
+#[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct KPageCgroup(u64);
+
+impl KPageCgroup {
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+unsafe impl FileReadable for KPageCgroup {}
+
+impl From<KPageCgroup> for u64 {
+    fn from(cgroup: KPageCgroup) -> u64 {
+        cgroup.0
+    }
+}
+
+/// Wrapper around a `Read` type that for the `/proc/kpagecgroup` file.
+pub type KPageCgroupReader<R> = FileReadableReader<R, KPageCgroup>;
+
+/// Turns a `KPageCgroupReader` into a proper (efficient) iterator over cgroup inode numbers.
+pub type KPageCgroupIterator<R> = FileReadableIterator<R, KPageCgroup>;
+
+/// Random-access reader for the `/proc/kpagecgroup` file, indexed by PFN.
+pub type KPageCgroupFile = FileReadableFile<KPageCgroup>;
+
+/// A memory-mapped dump of `/proc/kpagecgroup`, which derefs to `&[KPageCgroup]`.
+#[cfg(feature = "mmap")]
+pub type KPageCgroupMmap = crate::mmap::FileReadableMmap<KPageCgroup>;
+
+/// Maps cgroup inode numbers to paths in the cgroup v2 hierarchy.
+pub struct CgroupResolver {
+    paths: HashMap<u64, PathBuf>,
+}
+
+impl CgroupResolver {
+    /// Walks the hierarchy mounted at `CGROUP_ROOT`.
+    pub fn new() -> io::Result<Self> {
+        Self::from_root(CGROUP_ROOT)
+    }
+
+    /// Walks the hierarchy mounted at `root`. Cgroups removed during the walk are skipped.
+    pub fn from_root<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+        let root = root.as_ref();
+        let mut paths = HashMap::new();
+        paths.insert(std::fs::metadata(root)?.ino(), root.to_owned());
+        let mut to_visit = vec![root.to_owned()];
+
+        while let Some(dir) = to_visit.pop() {
+            let Some(entries) = unless_removed(std::fs::read_dir(&dir))? else {
+                continue;
+            };
+
+            for entry in entries {
+                let Some(entry) = unless_removed(entry)? else {
+                    continue;
+                };
+                let path = entry.path();
+                let Some(metadata) = unless_removed(std::fs::symlink_metadata(&path))? else {
+                    continue;
+                };
+
+                if metadata.is_dir() {
+                    paths.insert(metadata.ino(), path.clone());
+                    to_visit.push(path);
+                }
+            }
+        }
+
+        Ok(CgroupResolver { paths })
+    }
+
+    /// Returns the path of the cgroup with the given inode number, if it exists.
+    pub fn resolve(&self, cgroup: KPageCgroup) -> Option<&Path> {
+        self.paths.get(&cgroup.as_u64()).map(PathBuf::as_path)
+    }
+}
+
+/// Turns `NotFound` errors into `None`, for cgroups removed while we walk the hierarchy.
+fn unless_removed<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+    match result {
+        Ok(val) => Ok(Some(val)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Counts of pages in a few broad categories. A page may be counted in more than one category
+/// (e.g., THP pages are also anonymous).
+#[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub struct PageCategoryCounts {
+    pub total: u64,
+    pub anon: u64,
+    /// Page cache pages, i.e., non-anonymous pages on the LRU.
+    pub file: u64,
+    pub thp: u64,
+    pub slab: u64,
+    pub pgtable: u64,
+    pub buddy: u64,
+    pub reserved: u64,
+}
+
+impl PageCategoryCounts {
+    /// Counts a single page with the given flags.
+    pub fn add<K: Flaggy>(&mut self, flags: KPageFlags<K>) {
+        self.total += 1;
+
+        if flags.all(K::ANON) {
+            self.anon += 1;
+        } else if flags.all(K::LRU) {
+            self.file += 1;
+        }
+        if flags.all(K::THP) {
+            self.thp += 1;
+        }
+        if flags.all(K::SLAB) {
+            self.slab += 1;
+        }
+        if K::PGTABLE.is_some_and(|pgtable| flags.all(pgtable)) {
+            self.pgtable += 1;
+        }
+        if flags.all(K::BUDDY) {
+            self.buddy += 1;
+        }
+        if flags.all(K::RESERVED) {
+            self.reserved += 1;
+        }
+    }
+
+    /// Adds all counts from `other` to `self`.
+    pub fn merge(&mut self, other: &Self) {
+        self.total += other.total;
+        self.anon += other.anon;
+        self.file += other.file;
+        self.thp += other.thp;
+        self.slab += other.slab;
+        self.pgtable += other.pgtable;
+        self.buddy += other.buddy;
+        self.reserved += other.reserved;
+    }
+}
+
+/// Per-cgroup counts of pages by category, keyed by cgroup inode number.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CgroupUsage {
+    counts: BTreeMap<KPageCgroup, PageCategoryCounts>,
+}
+
+impl CgroupUsage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a single page with the given flags, charged to the given cgroup.
+    pub fn add<K: Flaggy>(&mut self, flags: KPageFlags<K>, cgroup: KPageCgroup) {
+        self.counts.entry(cgroup).or_default().add(flags);
+    }
+
+    /// Returns the counts for the given cgroup, if it has any pages.
+    pub fn get(&self, cgroup: KPageCgroup) -> Option<&PageCategoryCounts> {
+        self.counts.get(&cgroup)
+    }
+
+    /// Iterates over all cgroups with pages, in order of inode number.
+    pub fn iter(&self) -> impl Iterator<Item = (KPageCgroup, &PageCategoryCounts)> {
+        self.counts.iter().map(|(cgroup, counts)| (*cgroup, counts))
+    }
+
+    /// Like `iter`, but resolves the cgroups to paths. Cgroups that no longer exist (or pages not
+    /// charged to any cgroup) have no path.
+    pub fn iter_paths<'a>(
+        &'a self,
+        resolver: &'a CgroupResolver,
+    ) -> impl Iterator<Item = (Option<&'a Path>, &'a PageCategoryCounts)> {
+        self.iter()
+            .map(|(cgroup, counts)| (resolver.resolve(cgroup), counts))
+    }
+}
+
+impl<K: Flaggy> FromIterator<(KPageFlags<K>, KPageCgroup)> for CgroupUsage {
+    fn from_iter<I: IntoIterator<Item = (KPageFlags<K>, KPageCgroup)>>(iter: I) -> Self {
+        let mut usage = Self::new();
+        for (flags, cgroup) in iter {
+            usage.add(flags, cgroup);
+        }
+        usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use super::*;
+    use crate::kpageflags::{KPageFlagsIterator, KPageFlagsReader, KPF6_0_0};
+
+    #[test]
+    fn pages_are_counted_per_cgroup() {
+        let anon = u64::from(KPF6_0_0::Lru | KPF6_0_0::Anon);
+        let thp = u64::from(KPF6_0_0::CompoundHead | KPF6_0_0::Thp | KPF6_0_0::Anon);
+        let file = u64::from(KPF6_0_0::Lru);
+        let slab = u64::from(KPF6_0_0::Slab);
+        let pgtable = u64::from(KPF6_0_0::Pgtable);
+        let buddy = u64::from(KPF6_0_0::Buddy);
+        let records: [(u64, u64); 9] = [
+            (anon, 100),
+            (file, 100),
+            (file, 100),
+            (thp, 100),
+            (slab, 200),
+            (pgtable, 200),
+            (anon, 200),
+            (buddy, 0),
+            (file, 0),
+        ];
+
+        let bytes = |records: Vec<u64>| -> Vec<u8> {
+            records.into_iter().flat_map(u64::to_ne_bytes).collect()
+        };
+        let flags = bytes(records.iter().map(|(flags, _)| *flags).collect());
+        let cgroups = bytes(records.iter().map(|(_, cgroup)| *cgroup).collect());
+
+        let flags = KPageFlagsIterator::<_, KPF6_0_0::Flags>::new(
+            KPageFlagsReader::new(BufReader::new(flags.as_slice())),
+            &[],
+        );
+        let cgroups =
+            KPageCgroupIterator::new(KPageCgroupReader::new(BufReader::new(cgroups.as_slice())));
+        let usage: CgroupUsage = flags
+            .zip(cgroups)
+            .map(|(flags, cgroup)| (flags.unwrap(), cgroup.unwrap()))
+            .collect();
+
+        let counts: Vec<_> = usage
+            .iter()
+            .map(|(cgroup, counts)| {
+                (
+                    cgroup.as_u64(),
+                    counts.total,
+                    counts.anon,
+                    counts.file,
+                    counts.slab,
+                )
+            })
+            .collect();
+        assert_eq!(
+            counts,
+            [(0, 2, 0, 1, 0), (100, 4, 2, 2, 0), (200, 3, 1, 0, 1)]
+        );
+
+        let thp = usage.get(KPageCgroup(100)).unwrap().thp;
+        let pgtable = usage.get(KPageCgroup(200)).unwrap().pgtable;
+        let buddy = usage.get(KPageCgroup(0)).unwrap().buddy;
+        assert_eq!((thp, pgtable, buddy), (1, 1, 1));
+        assert_eq!(usage.get(KPageCgroup(300)), None);
+
+        let mut total = PageCategoryCounts::default();
+        usage.iter().for_each(|(_, counts)| total.merge(counts));
+        assert_eq!(
+            total,
+            PageCategoryCounts {
+                total: 9,
+                anon: 3,
+                file: 3,
+                thp: 1,
+                slab: 1,
+                pgtable: 1,
+                buddy: 1,
+                reserved: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn resolves_nested_cgroups() {
+        let root = tempfile::tempdir().unwrap();
+        let leaf = root.path().join("system.slice/foo.service");
+        std::fs::create_dir_all(&leaf).unwrap();
+        std::fs::write(root.path().join("memory.current"), "0\n").unwrap();
+
+        let resolver = CgroupResolver::from_root(root.path()).unwrap();
+        let ino = |path: &Path| KPageCgroup(std::fs::metadata(path).unwrap().ino());
+
+        assert_eq!(resolver.resolve(ino(root.path())), Some(root.path()));
+        assert_eq!(resolver.resolve(ino(&leaf)), Some(leaf.as_path()));
+        assert_eq!(resolver.paths.len(), 3);
+    }
+
+    #[test]
+    fn removed_cgroups_are_skipped() {
+        let root = tempfile::tempdir().unwrap();
+        let gone = root.path().join("gone");
+
+        assert!(unless_removed(std::fs::read_dir(&gone)).unwrap().is_none());
+        assert!(CgroupResolver::from_root(&gone).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Tools for reading `/proc/kpagecount`.
+
+use std::io::Read;
+
+use crate::{
+    kpageflags::{Flaggy, KPageFlags, KPageFlagsIterator},
+    Error, FileReadable, FileReadableFile, FileReadableIterator, FileReadableReader,
+};
+
+/// The file path... `/proc/kpagecount`.
+pub const KPAGECOUNT_PATH: &str = "/proc/kpagecount";
+
+/// The number of times a single physical page frame is mapped.
+#[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct KPageCount(u64);
+
+impl KPageCount {
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+unsafe impl FileReadable for KPageCount {}
+
+impl From<KPageCount> for u64 {
+    fn from(count: KPageCount) -> u64 {
+        count.0
+    }
+}
+
+impl std::fmt::Display for KPageCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Wrapper around a `Read` type that for the `/proc/kpagecount` file.
+pub type KPageCountReader<R> = FileReadableReader<R, KPageCount>;
+
+/// Turns a `KPageCountReader` into a proper (efficient) iterator over counts.
+pub type KPageCountIterator<R> = FileReadableIterator<R, KPageCount>;
+
+/// Random-access reader for the `/proc/kpagecount` file, indexed by PFN.
+pub type KPageCountFile = FileReadableFile<KPageCount>;
+
+/// A memory-mapped dump of `/proc/kpagecount`, which derefs to `&[KPageCount]`.
+#[cfg(feature = "mmap")]
+pub type KPageCountMmap = crate::mmap::FileReadableMmap<KPageCount>;
+
+/// Zips a `KPageFlagsIterator` and a `KPageCountIterator`, producing `(pfn, flags, mapcount)` for
+/// each physical page frame. Iteration stops when either iterator runs out.
+///
+/// An error from either iterator is returned once, and ends the iteration: the two streams can no
+/// longer be paired up reliably after it.
+pub struct KPageFlagsCountIterator<R1: Read, R2: Read, K: Flaggy> {
+    flags: KPageFlagsIterator<R1, K>,
+    counts: KPageCountIterator<R2>,
+    pfn: u64,
+    /// Whether either iterator has returned an error.
+    failed: bool,
+}
+
+impl<R1: Read, R2: Read, K: Flaggy> KPageFlagsCountIterator<R1, R2, K> {
+    pub fn new(flags: KPageFlagsIterator<R1, K>, counts: KPageCountIterator<R2>) -> Self {
+        KPageFlagsCountIterator {
+            flags,
+            counts,
+            pfn: 0,
+            failed: false,
+        }
+    }
+}
+
+impl<R1: Read, R2: Read, K: Flaggy> Iterator for KPageFlagsCountIterator<R1, R2, K> {
+    type Item = Result<(u64, KPageFlags<K>, u64), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let flags = match self.flags.next()? {
+            Ok(flags) => flags,
+            Err(err) => {
+                self.failed = true;
+                return Some(Err(err));
+            }
+        };
+        let count = match self.counts.next()? {
+            Ok(count) => count,
+            Err(err) => {
+                self.failed = true;
+                return Some(Err(err));
+            }
+        };
+        let pfn = self.pfn;
+
+        self.pfn += 1;
+
+        Some(Ok((pfn, flags, count.as_u64())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, BufReader};
+
+    use super::*;
+    use crate::kpageflags::{KPageFlagsReader, KPF6_0_0};
+
+    /// A reader that returns each chunk in turn from a separate call to `read`.
+    struct Chunks(Vec<io::Result<Vec<u8>>>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+
+            let chunk = self.0.remove(0)?;
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    fn chunk(records: &[u64]) -> io::Result<Vec<u8>> {
+        Ok(records
+            .iter()
+            .flat_map(|record| record.to_ne_bytes())
+            .collect())
+    }
+
+    fn error() -> io::Result<Vec<u8>> {
+        Err(io::Error::other("flaky"))
+    }
+
+    fn pairs(
+        flags: Vec<io::Result<Vec<u8>>>,
+        counts: Vec<io::Result<Vec<u8>>>,
+    ) -> Vec<Result<(u64, u64, u64), Error>> {
+        let flags = KPageFlagsIterator::<_, KPF6_0_0::Flags>::new(
+            KPageFlagsReader::new(BufReader::new(Chunks(flags))),
+            &[],
+        );
+        let counts = KPageCountIterator::new(KPageCountReader::new(BufReader::new(Chunks(counts))));
+
+        KPageFlagsCountIterator::new(flags, counts)
+            .map(|pair| pair.map(|(pfn, flags, count)| (pfn, flags.as_u64(), count)))
+            .collect()
+    }
+
+    #[test]
+    fn records_are_paired() {
+        let pairs = pairs(
+            vec![chunk(&[1, 2]), chunk(&[3])],
+            vec![chunk(&[4]), chunk(&[5, 6, 7])],
+        );
+        let pairs: Vec<_> = pairs.into_iter().map(Result::unwrap).collect();
+        assert_eq!(pairs, [(0, 1, 4), (1, 2, 5), (2, 3, 6)]);
+    }
+
+    #[test]
+    fn flags_errors_end_the_iteration() {
+        let pairs = pairs(
+            vec![chunk(&[1]), error(), chunk(&[2, 3])],
+            vec![chunk(&[4, 5, 6])],
+        );
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[0].as_ref().unwrap(), &(0, 1, 4));
+        assert!(matches!(pairs[1], Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn count_errors_end_the_iteration() {
+        let pairs = pairs(
+            vec![chunk(&[1, 2, 3])],
+            vec![chunk(&[4]), error(), chunk(&[5, 6])],
+        );
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[0].as_ref().unwrap(), &(0, 1, 4));
+        assert!(matches!(pairs[1], Err(Error::Io(_))));
+    }
+}
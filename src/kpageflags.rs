@@ -1,14 +1,36 @@
 //! Tools for reading `/proc/kpageflags`.
 
+mod diff;
+mod dynamic;
 mod flags;
+mod header;
+mod histogram;
+#[cfg(feature = "rayon")]
+mod parallel;
 mod read;
+mod regions;
+mod table;
+mod translate;
 
 use std::ops::{BitOr, BitOrAssign};
 
+pub use diff::{changed_regions, KPageFlagsDiff, Transition, TransitionMatrix};
+pub use dynamic::{DynFlags, DynamicLayout};
 pub use flags::{
-    Flaggy, KPF3_10_0, KPF4_15_0, KPF5_0_8, KPF5_13_0, KPF5_15_0, KPF5_17_0, KPF5_4_0, KPF6_0_0,
+    flag_table, layout_flags, layouts_with_flag, Canonical, CanonicalFlag, Flaggy, KPF3_10_0,
+    KPF4_15_0, KPF5_0_8, KPF5_13_0, KPF5_15_0, KPF5_17_0, KPF5_19_0, KPF5_4_0, KPF6_0_0, KPF6_12_0,
+    KPF6_1_0, KPF6_6_0,
 };
-pub use read::{KPageFlagsIterator, KPageFlagsReader};
+pub use header::{merge_kpf_defines, parse_kpf_defines, KpfDefine, TableEdits};
+pub use histogram::FlagHistogram;
+#[cfg(feature = "rayon")]
+pub use parallel::ParallelScan;
+#[cfg(feature = "mmap")]
+pub use read::KPageFlagsMmap;
+pub use read::{KPageFlagsFile, KPageFlagsIterator, KPageFlagsReader};
+pub use regions::{CombinePolicy, DefaultCombine, KPageFlagsRegions, Region};
+pub use table::FlagSpec;
+pub use translate::{translate, Translation};
 
 use crate::FileReadable;
 
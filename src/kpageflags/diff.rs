@@ -0,0 +1,389 @@
+//! Comparing two captures of `/proc/kpageflags` from the same machine.
+
+use std::{collections::HashMap, ops::Range};
+
+use super::{flags::Flaggy, histogram::human_size, KPageFlags};
+
+/// A page whose flags differ between two captures. Displayed as, e.g., `Buddy -> Slab`.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Transition<K: Flaggy> {
+    pub pfn: u64,
+    pub before: KPageFlags<K>,
+    pub after: KPageFlags<K>,
+}
+
+impl<K: Flaggy> std::fmt::Display for Transition<K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} -> {}", symbolic(self.before), symbolic(self.after))
+    }
+}
+
+/// Renders flags on one line, or `(none)` if no flags are set.
+fn symbolic<K: Flaggy>(flags: KPageFlags<K>) -> String {
+    let s = flags
+        .to_string()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if s.is_empty() {
+        "(none)".to_owned()
+    } else {
+        s
+    }
+}
+
+/// Zips two iterators over the flags of consecutive pages by PFN, and produces a `Transition` for
+/// each page whose flags differ.
+///
+/// If one capture is longer than the other (e.g., after memory hotplug), the missing pages of the
+/// shorter one are treated as `K::NOPAGE`.
+pub struct KPageFlagsDiff<I1, I2, K>
+where
+    I1: Iterator<Item = KPageFlags<K>>,
+    I2: Iterator<Item = KPageFlags<K>>,
+    K: Flaggy,
+{
+    before: I1,
+    after: I2,
+    /// The PFN of the next pages from `before` and `after`.
+    pfn: u64,
+}
+
+impl<I1, I2, K> KPageFlagsDiff<I1, I2, K>
+where
+    I1: Iterator<Item = KPageFlags<K>>,
+    I2: Iterator<Item = KPageFlags<K>>,
+    K: Flaggy,
+{
+    /// Compares `before` and `after`, which should both start at PFN `start_pfn`.
+    pub fn new(before: I1, after: I2, start_pfn: u64) -> Self {
+        KPageFlagsDiff {
+            before,
+            after,
+            pfn: start_pfn,
+        }
+    }
+}
+
+impl<I1, I2, K> Iterator for KPageFlagsDiff<I1, I2, K>
+where
+    I1: Iterator<Item = KPageFlags<K>>,
+    I2: Iterator<Item = KPageFlags<K>>,
+    K: Flaggy,
+{
+    type Item = Transition<K>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (before, after) = match (self.before.next(), self.after.next()) {
+                (None, None) => return None,
+                (before, after) => (
+                    before.unwrap_or(KPageFlags::from(K::NOPAGE)),
+                    after.unwrap_or(KPageFlags::from(K::NOPAGE)),
+                ),
+            };
+
+            let pfn = self.pfn;
+            self.pfn += 1;
+
+            if before != after {
+                return Some(Transition { pfn, before, after });
+            }
+        }
+    }
+}
+
+/// Coalesces the PFNs of a stream of transitions, in increasing order of PFN, into ranges of
+/// consecutive changed pages.
+pub fn changed_regions<K: Flaggy>(
+    transitions: impl IntoIterator<Item = Transition<K>>,
+) -> impl Iterator<Item = Range<u64>> {
+    let mut transitions = transitions.into_iter().peekable();
+
+    std::iter::from_fn(move || {
+        let first = transitions.next()?;
+        let mut region = first.pfn..first.pfn + 1;
+        while let Some(next) = transitions.next_if(|t| t.pfn == region.end) {
+            region.end = next.pfn + 1;
+        }
+        Some(region)
+    })
+}
+
+/// Counts pages per pair of flag combinations before and after, i.e., a sparse transition matrix.
+///
+/// The `Display` impl renders a table with the count and total size of each transition, most
+/// common first.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TransitionMatrix<K: Flaggy> {
+    counts: HashMap<(KPageFlags<K>, KPageFlags<K>), u64>,
+    /// Flags cleared from both sides of every transition before counting.
+    ignored_flags: K,
+    page_size: u64,
+}
+
+impl<K: Flaggy> TransitionMatrix<K> {
+    pub fn new() -> Self {
+        Self::with_ignored_flags(&[])
+    }
+
+    /// Creates a matrix that clears the given flags from both sides of every transition before
+    /// counting it. Transitions that only involve ignored flags are not counted at all.
+    pub fn with_ignored_flags(ignored_flags: &[K]) -> Self {
+        TransitionMatrix {
+            counts: HashMap::new(),
+            ignored_flags: ignored_flags.iter().fold(K::empty(), |a, b| a | *b),
+            page_size: crate::page_size() as u64,
+        }
+    }
+
+    /// Sets the page size used to render sizes. Defaults to the page size of this system.
+    pub fn with_page_size(mut self, page_size: u64) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Counts `npages` pages going from `before` to `after`.
+    pub fn add_n(&mut self, mut before: KPageFlags<K>, mut after: KPageFlags<K>, npages: u64) {
+        before.clear(self.ignored_flags);
+        after.clear(self.ignored_flags);
+        if before != after {
+            *self.counts.entry((before, after)).or_insert(0) += npages;
+        }
+    }
+
+    /// Counts a single transition.
+    pub fn add(&mut self, transition: Transition<K>) {
+        self.add_n(transition.before, transition.after, 1);
+    }
+
+    /// Returns the number of pages going from exactly `before` to exactly `after`.
+    pub fn get(&self, before: KPageFlags<K>, after: KPageFlags<K>) -> u64 {
+        self.counts.get(&(before, after)).copied().unwrap_or(0)
+    }
+
+    /// Returns the total number of changed pages counted.
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// Returns the number of distinct transitions.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Iterates over the transitions as `(before, after, count)` in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (KPageFlags<K>, KPageFlags<K>, u64)> + '_ {
+        self.counts
+            .iter()
+            .map(|((before, after), count)| (*before, *after, *count))
+    }
+
+    /// Returns the transitions and their counts, most common first. Ties are broken by flags.
+    pub fn sorted_by_count(&self) -> Vec<(KPageFlags<K>, KPageFlags<K>, u64)> {
+        let mut sorted: Vec<_> = self.iter().collect();
+        sorted.sort_by(|(ba, aa, ca), (bb, ab, cb)| cb.cmp(ca).then((ba, aa).cmp(&(bb, ab))));
+        sorted
+    }
+}
+
+impl<K: Flaggy> Default for TransitionMatrix<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Flaggy> Extend<Transition<K>> for TransitionMatrix<K> {
+    fn extend<I: IntoIterator<Item = Transition<K>>>(&mut self, iter: I) {
+        for transition in iter {
+            self.add(transition);
+        }
+    }
+}
+
+impl<K: Flaggy> FromIterator<Transition<K>> for TransitionMatrix<K> {
+    fn from_iter<I: IntoIterator<Item = Transition<K>>>(iter: I) -> Self {
+        let mut matrix = Self::new();
+        matrix.extend(iter);
+        matrix
+    }
+}
+
+impl<K: Flaggy> std::fmt::Display for TransitionMatrix<K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:>18} {:>18} {:>12} {:>10}  symbolic-flags",
+            "before", "after", "page-count", "size"
+        )?;
+
+        for (before, after, count) in self.sorted_by_count() {
+            writeln!(
+                f,
+                "0x{:016x} 0x{:016x} {:>12} {:>10}  {} -> {}",
+                before.as_u64(),
+                after.as_u64(),
+                count,
+                human_size(count * self.page_size),
+                symbolic(before),
+                symbolic(after),
+            )?;
+        }
+
+        let total = self.total();
+        writeln!(
+            f,
+            "{:>37} {:>12} {:>10}",
+            "total",
+            total,
+            human_size(total * self.page_size)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Cursor};
+
+    use super::*;
+    use crate::kpageflags::{KPageFlagsIterator, KPageFlagsReader, KPF6_0_0};
+
+    type Flags = KPF6_0_0::Flags;
+
+    fn pages(flags: &[Flags]) -> Vec<KPageFlags<Flags>> {
+        flags.iter().map(|flags| KPageFlags::from(*flags)).collect()
+    }
+
+    fn diff(before: &[Flags], after: &[Flags]) -> Vec<Transition<Flags>> {
+        KPageFlagsDiff::new(pages(before).into_iter(), pages(after).into_iter(), 10).collect()
+    }
+
+    #[test]
+    fn changed_pages_are_reported() {
+        let transitions = diff(
+            &[KPF6_0_0::Buddy, KPF6_0_0::Lru, KPF6_0_0::Slab],
+            &[KPF6_0_0::Slab, KPF6_0_0::Lru, KPF6_0_0::Buddy],
+        );
+
+        let pfns: Vec<_> = transitions.iter().map(|t| t.pfn).collect();
+        assert_eq!(pfns, [10, 12]);
+        assert_eq!(transitions[0].to_string(), "Buddy -> Slab");
+        assert_eq!(transitions[1].to_string(), "Slab -> Buddy");
+    }
+
+    #[test]
+    fn missing_pages_are_nopage() {
+        let longer = [KPF6_0_0::Buddy, KPF6_0_0::Lru, KPF6_0_0::Nopage];
+
+        let shrunk = diff(&longer, &longer[..1]);
+        let shrunk: Vec<_> = shrunk.iter().map(|t| (t.pfn, t.after)).collect();
+        assert_eq!(shrunk, [(11, KPageFlags::from(KPF6_0_0::Nopage))]);
+
+        let grown = diff(&longer[..1], &longer);
+        let grown: Vec<_> = grown.iter().map(|t| (t.pfn, t.before)).collect();
+        assert_eq!(grown, [(11, KPageFlags::from(KPF6_0_0::Nopage))]);
+
+        assert!(diff(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn ignored_flags_are_unchanged() {
+        let read = |flags: &[Flags]| {
+            let bytes: Vec<u8> = flags
+                .iter()
+                .flat_map(|f| u64::from(*f).to_ne_bytes())
+                .collect();
+            let reader = KPageFlagsReader::<_, Flags>::new(BufReader::new(Cursor::new(bytes)));
+            KPageFlagsIterator::new(reader, &[KPF6_0_0::Referenced, KPF6_0_0::Idle])
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+
+        let before = read(&[KPF6_0_0::Lru | KPF6_0_0::Referenced, KPF6_0_0::Lru]);
+        let after = read(&[KPF6_0_0::Lru, KPF6_0_0::Lru | KPF6_0_0::Idle]);
+        let transitions = KPageFlagsDiff::new(before.into_iter(), after.into_iter(), 0);
+        assert_eq!(transitions.count(), 0);
+
+        let mut matrix = TransitionMatrix::with_ignored_flags(&[KPF6_0_0::Referenced]);
+        let lru = KPageFlags::from(KPF6_0_0::Lru);
+        let referenced = KPageFlags::from(KPF6_0_0::Lru | KPF6_0_0::Referenced);
+        matrix.add_n(lru, referenced, 5);
+        assert!(matrix.is_empty());
+        matrix.add_n(referenced, KPageFlags::from(KPF6_0_0::Buddy), 5);
+        assert_eq!(matrix.get(lru, KPageFlags::from(KPF6_0_0::Buddy)), 5);
+    }
+
+    #[test]
+    fn neighbouring_changes_are_one_region() {
+        let transitions = diff(
+            &[
+                KPF6_0_0::Buddy,
+                KPF6_0_0::Buddy,
+                KPF6_0_0::Lru,
+                KPF6_0_0::Buddy,
+                KPF6_0_0::Buddy,
+            ],
+            &[
+                KPF6_0_0::Slab,
+                KPF6_0_0::Slab,
+                KPF6_0_0::Lru,
+                KPF6_0_0::Slab,
+                KPF6_0_0::Anon,
+            ],
+        );
+
+        let regions: Vec<_> = changed_regions(transitions).collect();
+        assert_eq!(regions, [10..12, 13..15]);
+        assert_eq!(changed_regions::<Flags>([]).count(), 0);
+    }
+
+    #[test]
+    fn transitions_are_counted() {
+        let transitions = diff(
+            &[
+                KPF6_0_0::Buddy,
+                KPF6_0_0::Buddy,
+                KPF6_0_0::Lru,
+                KPF6_0_0::Buddy,
+                KPF6_0_0::Slab,
+            ],
+            &[
+                KPF6_0_0::Slab,
+                KPF6_0_0::Slab,
+                KPF6_0_0::Lru,
+                KPF6_0_0::Lru,
+                KPF6_0_0::Buddy,
+            ],
+        );
+        let matrix: TransitionMatrix<Flags> = transitions.into_iter().collect();
+
+        let flags = |f| KPageFlags::from(f);
+        assert_eq!(matrix.total(), 4);
+        assert_eq!(matrix.len(), 3);
+        assert_eq!(matrix.get(flags(KPF6_0_0::Buddy), flags(KPF6_0_0::Slab)), 2);
+        assert_eq!(matrix.get(flags(KPF6_0_0::Buddy), flags(KPF6_0_0::Lru)), 1);
+        assert_eq!(matrix.get(flags(KPF6_0_0::Slab), flags(KPF6_0_0::Buddy)), 1);
+        assert_eq!(matrix.get(flags(KPF6_0_0::Lru), flags(KPF6_0_0::Lru)), 0);
+
+        let sorted = matrix.sorted_by_count();
+        assert_eq!(
+            sorted[0],
+            (flags(KPF6_0_0::Buddy), flags(KPF6_0_0::Slab), 2)
+        );
+
+        let table = matrix.with_page_size(4096).to_string();
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(
+            lines[1].ends_with(" 2    8.0 KiB  Buddy -> Slab"),
+            "{}",
+            lines[1]
+        );
+        assert!(lines[4].trim_start().starts_with("total"));
+    }
+}
@@ -0,0 +1,627 @@
+//! Flag layouts defined at runtime, for kernels that don't match any built-in `KPF*` layout
+//! (e.g., vendor kernels with backported or private bits).
+//!
+//! A `DynamicLayout` is loaded from a list of flag names and bits (or a TOML or JSON file, with
+//! the `toml` and `json` features) and then installed for the whole process. After that,
+//! `DynFlags` is a `Flaggy` type like any other, so it works with `KPageFlagsIterator`,
+//! `FlagHistogram`, etc.
+
+use std::{
+    ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not},
+    str::FromStr,
+    sync::OnceLock,
+};
+
+use crate::{kernel::KernelLayout, Error};
+
+use super::{flags::Flaggy, layout_flags};
+
+/// The flags of the installed `DynamicLayout`, as raw bits of `/proc/kpageflags`.
+///
+/// There is only one layout per process: `DynamicLayout::install` sets it once and for all, so
+/// captures from several unknown kernels can't be read side by side as `DynFlags` (translate them
+/// to `Canonical` one at a time instead). Until a layout is installed, `values()`, `name()`,
+/// `FromStr`, and `Debug` only know the flags behind the `Flaggy` constants, and `From<u64>`
+/// panics on any other bit.
+#[derive(Copy, Clone, Hash, PartialEq, PartialOrd, Eq, Ord)]
+#[repr(transparent)]
+pub struct DynFlags(u64);
+
+impl DynFlags {
+    /// Returns the raw bits.
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+}
+
+/// The flags behind the `Flaggy` constants, which are at the same bits in every known kernel. A
+/// `DynamicLayout` must put them at these bits, too.
+const COMMON: &[(&str, DynFlags)] = &[
+    ("Nopage", DynFlags::NOPAGE),
+    ("CompoundHead", DynFlags::COMPOUND_HEAD),
+    ("CompoundTail", DynFlags::COMPOUND_TAIL),
+    ("Buddy", DynFlags::BUDDY),
+    ("Slab", DynFlags::SLAB),
+    ("Reserved", DynFlags::RESERVED),
+    ("Mmap", DynFlags::MMAP),
+    ("Lru", DynFlags::LRU),
+    ("Anon", DynFlags::ANON),
+    ("Thp", DynFlags::THP),
+    ("Idle", DynFlags::IDLE),
+    ("Private", DynFlags::PRIVATE),
+    ("Private2", DynFlags::PRIVATE2),
+    ("OwnerPrivate", DynFlags::OWNERPRIVATE1),
+];
+
+const PGTABLE: DynFlags = DynFlags(1 << 26);
+const OFFLINE: DynFlags = DynFlags(1 << 23);
+
+/// Like `COMMON`, but for the `Option` constants. These flags may be missing, but if present must
+/// be at these bits.
+const OPTIONAL: &[(&str, DynFlags)] = &[("Pgtable", PGTABLE), ("Offline", OFFLINE)];
+
+/// The installed layout, if any.
+static INSTALLED: OnceLock<Installed> = OnceLock::new();
+
+struct Installed {
+    layout: DynamicLayout,
+    values: Vec<DynFlags>,
+}
+
+/// The `values()` of `DynFlags` before a layout is installed: just the common flags.
+static COMMON_VALUES: OnceLock<Vec<DynFlags>> = OnceLock::new();
+
+/// A set of flag names and bits, defined at runtime.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct DynamicLayout {
+    name: String,
+    /// Sorted by bit.
+    flags: Vec<(String, u32)>,
+}
+
+impl DynamicLayout {
+    /// Creates a layout with the given flags and bits. `name` is only for display, e.g., the
+    /// kernel release.
+    ///
+    /// Fails if a name or bit is repeated, a bit is out of range, or one of the flags behind the
+    /// `Flaggy` constants (e.g., `Buddy`) is missing or not at its usual bit. A different flag at
+    /// the bit of `Pgtable` or `Offline` (e.g., `Balloon` on old kernels) is allowed, but will
+    /// match `PGTABLE` or `OFFLINE`.
+    pub fn new(
+        name: impl Into<String>,
+        flags: impl IntoIterator<Item = (String, u32)>,
+    ) -> Result<Self, Error> {
+        let invalid = |reason: String| Err(Error::InvalidLayout(reason));
+
+        let mut flags: Vec<_> = flags.into_iter().collect();
+        flags.sort_by_key(|(_, bit)| *bit);
+
+        for (i, (name, bit)) in flags.iter().enumerate() {
+            if *bit >= 64 {
+                return invalid(format!(
+                    "{} is at bit {}, past the end of the word",
+                    name, bit
+                ));
+            }
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return invalid(format!("invalid flag name: {:?}", name));
+            }
+            if let Some((other, _)) = flags[..i].iter().find(|(other, _)| other == name) {
+                return invalid(format!("{} is defined twice", other));
+            }
+            if let Some((other, _)) = flags[..i].iter().find(|(_, other)| other == bit) {
+                return invalid(format!("{} and {} are both at bit {}", other, name, bit));
+            }
+        }
+
+        for (common, flag) in COMMON.iter().chain(OPTIONAL.iter()) {
+            let expected = flag.0.trailing_zeros();
+            match flags.iter().find(|(name, _)| name == common) {
+                Some((_, bit)) if *bit != expected => {
+                    return invalid(format!(
+                        "{} must be at bit {}, not {}",
+                        common, expected, bit
+                    ));
+                }
+                None if COMMON.iter().any(|(name, _)| name == common) => {
+                    return invalid(format!("{} is missing", common));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(DynamicLayout {
+            name: name.into(),
+            flags,
+        })
+    }
+
+    /// Returns a copy of a built-in layout, e.g., as a starting point for a vendor kernel.
+    pub fn from_kernel_layout(layout: KernelLayout) -> Self {
+        let flags = layout_flags(layout).map(|flag| (flag.name.to_owned(), flag.bit));
+        Self::new(layout.version().to_string(), flags).unwrap()
+    }
+
+    /// Parses a layout from a TOML document like the following. `name` is optional, and other
+    /// fields are rejected.
+    ///
+    /// ```toml
+    /// name = "5.14.0-362.el9"
+    ///
+    /// [flags]
+    /// Locked = 0
+    /// Error = 1
+    /// # ...
+    /// ```
+    #[cfg(feature = "toml")]
+    pub fn from_toml(s: &str) -> Result<Self, Error> {
+        let table: toml::Table = s.parse().map_err(|err: toml::de::Error| {
+            Error::InvalidLayout(err.to_string().trim_end().to_owned())
+        })?;
+        Self::check_fields(table.keys())?;
+
+        let name = match table.get("name") {
+            None => None,
+            Some(toml::Value::String(name)) => Some(name.as_str()),
+            Some(_) => return Err(Error::InvalidLayout("name is not a string".into())),
+        };
+        let Some(toml::Value::Table(flags)) = table.get("flags") else {
+            return Err(Error::InvalidLayout("missing [flags] table".into()));
+        };
+
+        Self::from_entries(
+            name,
+            flags
+                .iter()
+                .map(|(flag, bit)| (flag.as_str(), bit.as_integer())),
+        )
+    }
+
+    /// Parses a layout from a JSON document like `{"name": "...", "flags": {"Locked": 0, ...}}`.
+    /// `name` is optional, and other fields are rejected.
+    #[cfg(feature = "json")]
+    pub fn from_json(s: &str) -> Result<Self, Error> {
+        let value: serde_json::Value =
+            serde_json::from_str(s).map_err(|err| Error::InvalidLayout(err.to_string()))?;
+        let Some(fields) = value.as_object() else {
+            return Err(Error::InvalidLayout("layout is not an object".into()));
+        };
+        Self::check_fields(fields.keys())?;
+
+        let name = match value.get("name") {
+            None => None,
+            Some(serde_json::Value::String(name)) => Some(name.as_str()),
+            Some(_) => return Err(Error::InvalidLayout("name is not a string".into())),
+        };
+        let Some(serde_json::Value::Object(flags)) = value.get("flags") else {
+            return Err(Error::InvalidLayout("missing flags object".into()));
+        };
+
+        Self::from_entries(
+            name,
+            flags
+                .iter()
+                .map(|(flag, bit)| (flag.as_str(), bit.as_i64())),
+        )
+    }
+
+    /// Fails on any top-level field other than `name` and `flags`, e.g., a misspelled one.
+    #[cfg(any(feature = "toml", feature = "json"))]
+    fn check_fields<'a>(mut fields: impl Iterator<Item = &'a String>) -> Result<(), Error> {
+        match fields.find(|field| *field != "name" && *field != "flags") {
+            Some(field) => Err(Error::InvalidLayout(format!("unknown field: {}", field))),
+            None => Ok(()),
+        }
+    }
+
+    /// Builds a layout from parsed `(name, bit)` entries, where `None` is a bit that isn't an
+    /// integer.
+    #[cfg(any(feature = "toml", feature = "json"))]
+    fn from_entries<'a>(
+        name: Option<&str>,
+        entries: impl Iterator<Item = (&'a str, Option<i64>)>,
+    ) -> Result<Self, Error> {
+        let flags = entries
+            .map(
+                |(flag, bit)| match bit.and_then(|bit| u32::try_from(bit).ok()) {
+                    Some(bit) => Ok((flag.to_owned(), bit)),
+                    None => Err(Error::InvalidLayout(format!("invalid bit for {}", flag))),
+                },
+            )
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::new(name.unwrap_or("dynamic"), flags)
+    }
+
+    /// Reads a layout from a `.toml` or `.json` file, as given by its extension.
+    #[cfg(any(feature = "toml", feature = "json"))]
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            #[cfg(feature = "toml")]
+            Some("toml") => Self::from_toml(&contents),
+            #[cfg(feature = "json")]
+            Some("json") => Self::from_json(&contents),
+            _ => Err(Error::InvalidLayout(format!(
+                "unsupported layout file: {}",
+                path.display()
+            ))),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Iterates over the names and bits of the flags, in order of bit.
+    pub fn flags(&self) -> impl Iterator<Item = (&str, u32)> + '_ {
+        self.flags.iter().map(|(name, bit)| (name.as_str(), *bit))
+    }
+
+    /// Returns the flag with the given name, if any.
+    pub fn get(&self, name: &str) -> Option<DynFlags> {
+        self.flags
+            .iter()
+            .find(|(flag, _)| flag == name)
+            .map(|(_, bit)| DynFlags(1 << bit))
+    }
+
+    /// Returns the mask of all flags in the layout.
+    pub fn valid_mask(&self) -> u64 {
+        self.flags.iter().fold(0, |mask, (_, bit)| mask | 1 << bit)
+    }
+
+    /// Makes this the layout of `DynFlags` for the rest of the process. Only one layout can be
+    /// installed; if one already is, this layout is returned as the error.
+    pub fn install(self) -> Result<&'static DynamicLayout, Self> {
+        let values = self
+            .flags
+            .iter()
+            .map(|(_, bit)| DynFlags(1 << bit))
+            .collect();
+        let mut installed = Some(Installed {
+            layout: self,
+            values,
+        });
+
+        let result = INSTALLED.get_or_init(|| installed.take().unwrap());
+        match installed {
+            None => Ok(&result.layout),
+            Some(rejected) => Err(rejected.layout),
+        }
+    }
+
+    /// Returns the installed layout, if any.
+    pub fn installed() -> Option<&'static DynamicLayout> {
+        INSTALLED.get().map(|installed| &installed.layout)
+    }
+}
+
+impl FromStr for DynFlags {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match DynamicLayout::installed() {
+            Some(layout) => layout.get(s),
+            None => COMMON
+                .iter()
+                .find(|(name, _)| *name == s)
+                .map(|(_, flag)| *flag),
+        }
+        .ok_or_else(|| format!("unknown flag: {}", s))
+    }
+}
+
+impl Flaggy for DynFlags {
+    const NOPAGE: Self = DynFlags(1 << 20);
+    const COMPOUND_HEAD: Self = DynFlags(1 << 15);
+    const COMPOUND_TAIL: Self = DynFlags(1 << 16);
+    const PGTABLE: Option<Self> = Some(PGTABLE);
+    const OFFLINE: Option<Self> = Some(OFFLINE);
+    const BUDDY: Self = DynFlags(1 << 10);
+    const SLAB: Self = DynFlags(1 << 7);
+    const RESERVED: Self = DynFlags(1 << 32);
+    const MMAP: Self = DynFlags(1 << 11);
+    const LRU: Self = DynFlags(1 << 5);
+    const ANON: Self = DynFlags(1 << 12);
+    const THP: Self = DynFlags(1 << 22);
+    const IDLE: Self = DynFlags(1 << 25);
+    const PRIVATE: Self = DynFlags(1 << 35);
+    const PRIVATE2: Self = DynFlags(1 << 36);
+    const OWNERPRIVATE1: Self = DynFlags(1 << 37);
+
+    fn empty() -> Self {
+        DynFlags(0)
+    }
+
+    /// The flags of the installed layout, or just the common flags if none is installed.
+    fn values() -> &'static [Self] {
+        match INSTALLED.get() {
+            Some(installed) => &installed.values,
+            None => COMMON_VALUES.get_or_init(|| {
+                let mut values: Vec<_> = COMMON.iter().map(|(_, flag)| *flag).collect();
+                values.sort();
+                values
+            }),
+        }
+    }
+
+    fn name(self) -> Option<&'static str> {
+        if self.0.count_ones() != 1 {
+            return None;
+        }
+        let bit = self.0.trailing_zeros();
+
+        match DynamicLayout::installed() {
+            Some(layout) => layout
+                .flags
+                .iter()
+                .find(|(_, other)| *other == bit)
+                .map(|(name, _)| name.as_str()),
+            None => COMMON
+                .iter()
+                .find(|(_, flag)| *flag == self)
+                .map(|(name, _)| *name),
+        }
+    }
+}
+
+impl From<DynFlags> for u64 {
+    fn from(kpf: DynFlags) -> u64 {
+        kpf.0
+    }
+}
+
+impl From<u64> for DynFlags {
+    /// Panics if `val` has bits that aren't flags of the installed layout, or of the `Flaggy`
+    /// constants if no layout is installed yet.
+    fn from(val: u64) -> Self {
+        let unknown = val & !Self::valid_mask().0;
+        if unknown != 0 {
+            match DynamicLayout::installed() {
+                Some(layout) => panic!("{:#x} are not flags of layout {}", unknown, layout.name),
+                None => panic!(
+                    "{:#x} are not flags of any layout: no DynamicLayout is installed",
+                    unknown
+                ),
+            }
+        }
+        DynFlags(val)
+    }
+}
+
+impl std::fmt::Debug for DynFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Box<dyn Iterator<Item = (&str, u32)>> = match DynamicLayout::installed() {
+            Some(layout) => Box::new(layout.flags()),
+            None => Box::new(
+                COMMON
+                    .iter()
+                    .map(|(name, flag)| (*name, flag.0.trailing_zeros())),
+            ),
+        };
+
+        for (name, bit) in names {
+            if self.0 & (1 << bit) != 0 {
+                write!(f, "{} ", name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl BitOr for DynFlags {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        DynFlags(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for DynFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        *self = *self | rhs;
+    }
+}
+
+impl BitAnd for DynFlags {
+    type Output = Self;
+    fn bitand(self, rhs: Self) -> Self {
+        DynFlags(self.0 & rhs.0)
+    }
+}
+
+impl BitAndAssign for DynFlags {
+    fn bitand_assign(&mut self, rhs: Self) {
+        *self = *self & rhs;
+    }
+}
+
+impl BitXor for DynFlags {
+    type Output = Self;
+    fn bitxor(self, rhs: Self) -> Self {
+        DynFlags(self.0 ^ rhs.0)
+    }
+}
+
+impl BitXorAssign for DynFlags {
+    fn bitxor_assign(&mut self, rhs: Self) {
+        *self = *self ^ rhs;
+    }
+}
+
+impl Not for DynFlags {
+    type Output = Self;
+    fn not(self) -> Self {
+        DynFlags(!self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::catch_unwind;
+
+    use super::*;
+
+    fn panic_message<T: std::fmt::Debug>(f: impl FnOnce() -> T + std::panic::UnwindSafe) -> String {
+        let payload = catch_unwind(f).unwrap_err();
+        payload
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn invalid_layouts_are_rejected() {
+        let layout = |flags: &[(&str, u32)]| {
+            let flags = flags.iter().map(|(name, bit)| (name.to_string(), *bit));
+            DynamicLayout::new("test", flags)
+        };
+        let common: Vec<_> = COMMON
+            .iter()
+            .map(|(name, flag)| (*name, flag.0.trailing_zeros()))
+            .collect();
+
+        assert!(layout(&common).is_ok());
+        assert!(layout(&common[1..]).is_err());
+        assert!(layout(&[&common[..], &[("Foo", 64)]].concat()).is_err());
+        assert!(layout(&[&common[..], &[("Foo", 50), ("Bar", 50)]].concat()).is_err());
+        assert!(layout(&[&common[..], &[("Foo", 50), ("Foo", 51)]].concat()).is_err());
+        assert!(layout(&[&common[..], &[("Offline", 24)]].concat()).is_err());
+    }
+
+    /// Returns the reason a layout is invalid.
+    #[cfg(any(feature = "toml", feature = "json"))]
+    fn reason(layout: Result<DynamicLayout, Error>) -> String {
+        match layout {
+            Err(Error::InvalidLayout(reason)) => reason,
+            layout => panic!("expected an invalid layout, got {:?}", layout),
+        }
+    }
+
+    /// Renders a TOML layout with the common flags, plus the `fields` and `flags` lines.
+    #[cfg(feature = "toml")]
+    fn toml_layout(fields: &str, flags: &str) -> String {
+        let common: String = COMMON
+            .iter()
+            .map(|(name, flag)| format!("{} = {}\n", name, flag.0.trailing_zeros()))
+            .collect();
+        format!(
+            "name = \"vendor\"\n{}\n[flags]\n{}{}",
+            fields, common, flags
+        )
+    }
+
+    /// Renders a JSON layout with the common flags, plus the `fields` and `flags` members, each
+    /// followed by a comma.
+    #[cfg(feature = "json")]
+    fn json_layout(fields: &str, flags: &str) -> String {
+        let common: Vec<_> = COMMON
+            .iter()
+            .map(|(name, flag)| format!("\"{}\": {}", name, flag.0.trailing_zeros()))
+            .collect();
+        format!(
+            "{{{}\"name\": \"vendor\", \"flags\": {{{}{}}}}}",
+            fields,
+            flags,
+            common.join(", ")
+        )
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml_layouts_are_parsed() {
+        let layout =
+            |fields: &str, flags: &str| DynamicLayout::from_toml(&toml_layout(fields, flags));
+
+        let valid = layout("", "Vendor = 45\n").unwrap();
+        assert_eq!(valid.name(), "vendor");
+        assert_eq!(valid.get("Vendor"), Some(DynFlags(1 << 45)));
+        assert_eq!(valid.flags().count(), COMMON.len() + 1);
+
+        assert!(reason(layout("", "Foo = 50\nBar = 50\n")).contains("both at bit"));
+        assert!(reason(layout("", "Foo = 64\n")).contains("past the end"));
+        assert!(reason(layout("", "Foo = -1\n")).contains("invalid bit"));
+        assert!(reason(layout("nmae = \"typo\"\n", "")).contains("unknown field"));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_layouts_are_parsed() {
+        let layout =
+            |fields: &str, flags: &str| DynamicLayout::from_json(&json_layout(fields, flags));
+
+        let valid = layout("", "\"Vendor\": 45, ").unwrap();
+        assert_eq!(valid.name(), "vendor");
+        assert_eq!(valid.get("Vendor"), Some(DynFlags(1 << 45)));
+        assert_eq!(valid.flags().count(), COMMON.len() + 1);
+
+        assert!(reason(layout("", "\"Foo\": 50, \"Bar\": 50, ")).contains("both at bit"));
+        assert!(reason(layout("", "\"Foo\": 64, ")).contains("past the end"));
+        assert!(reason(layout("", "\"Foo\": 4.5, ")).contains("invalid bit"));
+        assert!(reason(layout("\"nmae\": \"typo\", ", "")).contains("unknown field"));
+        assert!(reason(DynamicLayout::from_json("[]")).contains("not an object"));
+    }
+
+    #[cfg(all(feature = "toml", feature = "json"))]
+    #[test]
+    fn layout_files_are_opened_by_extension() {
+        use std::io::Write;
+
+        let open = |suffix: &str, contents: &str| {
+            let mut file = tempfile::Builder::new().suffix(suffix).tempfile().unwrap();
+            file.write_all(contents.as_bytes()).unwrap();
+            DynamicLayout::open(file.path())
+        };
+
+        let toml = toml_layout("", "");
+        let json = json_layout("", "");
+        assert_eq!(open(".toml", &toml).unwrap().name(), "vendor");
+        assert_eq!(open(".json", &json).unwrap().name(), "vendor");
+
+        let toml = toml_layout("", "Foo = 64\n");
+        let json = json_layout("\"nmae\": \"typo\", ", "");
+        assert!(reason(open(".toml", &toml)).contains("past the end"));
+        assert!(reason(open(".json", &json)).contains("unknown field"));
+
+        assert!(matches!(open(".json", &toml), Err(Error::InvalidLayout(_))));
+        assert!(matches!(open(".toml", &json), Err(Error::InvalidLayout(_))));
+        assert!(matches!(open(".yaml", &toml), Err(Error::InvalidLayout(_))));
+        assert!(matches!(
+            DynamicLayout::open("/nonexistent/layout.toml"),
+            Err(Error::Io(_))
+        ));
+    }
+
+    /// The layout is global, so everything that depends on whether it is installed is tested here,
+    /// in order.
+    #[test]
+    fn installed_layout() {
+        let extra = 1 << 45;
+
+        // Before installing, only the common flags are known.
+        assert_eq!(DynFlags::values().len(), COMMON.len());
+        assert_eq!("Buddy".parse::<DynFlags>(), Ok(DynFlags::BUDDY));
+        assert!("Vendor".parse::<DynFlags>().is_err());
+        assert_eq!(DynFlags::BUDDY.name(), Some("Buddy"));
+        assert_eq!(DynFlags::from(DynFlags::LRU.bits()), DynFlags::LRU);
+        assert!(panic_message(|| DynFlags::from(extra)).contains("no DynamicLayout"));
+
+        let mut layout = DynamicLayout::from_kernel_layout(KernelLayout::V6_12_0);
+        layout.name = "vendor".into();
+        layout.flags.push(("Vendor".into(), 45));
+        let layout = DynamicLayout::new(layout.name, layout.flags).unwrap();
+        let installed = layout.clone().install().unwrap();
+        assert_eq!(*installed, layout);
+        assert_eq!(layout.clone().install(), Err(layout));
+
+        let vendor: DynFlags = "Vendor".parse().unwrap();
+        assert_eq!(vendor.bits(), extra);
+        assert_eq!(vendor.name(), Some("Vendor"));
+        assert_eq!(DynFlags::from(extra), vendor);
+        assert_eq!(format!("{:?}", vendor | DynFlags::BUDDY), "Buddy Vendor ");
+        assert!(panic_message(|| DynFlags::from(1 << 46)).contains("layout vendor"));
+    }
+}
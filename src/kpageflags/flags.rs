@@ -5,6 +5,10 @@ use std::{
     str::FromStr,
 };
 
+use crate::kernel::{KernelLayout, KernelVersion};
+
+use super::table::{FlagSpec, FLAGS, LAYOUTS};
+
 /// All the different KPF implementations are `Flaggy`.
 pub trait Flaggy:
     Sized
@@ -30,6 +34,7 @@ pub trait Flaggy:
     const COMPOUND_HEAD: Self;
     const COMPOUND_TAIL: Self;
     const PGTABLE: Option<Self>;
+    const OFFLINE: Option<Self>;
     const BUDDY: Self;
     const SLAB: Self;
     const RESERVED: Self;
@@ -37,6 +42,7 @@ pub trait Flaggy:
     const LRU: Self;
     const ANON: Self;
     const THP: Self;
+    const IDLE: Self;
     const PRIVATE: Self;
     const PRIVATE2: Self;
     const OWNERPRIVATE1: Self;
@@ -44,6 +50,15 @@ pub trait Flaggy:
     fn empty() -> Self;
     fn values() -> &'static [Self];
 
+    /// Returns the name of a single flag, e.g., `"Buddy"`, or `None` if `self` is not exactly one
+    /// of `values()`. Translating between layouts goes by these names (see `Translation`).
+    ///
+    /// The default knows no names, so all flags of an implementation that doesn't override it are
+    /// dropped by translations.
+    fn name(self) -> Option<&'static str> {
+        None
+    }
+
     fn valid_mask() -> Self {
         Self::values().iter().fold(Self::empty(), |a, b| a | *b)
     }
@@ -70,6 +85,13 @@ macro_rules! kpf {
                 pub const $name : Flags = Flags(1 << $val);
             )+
 
+            impl Flags {
+                /// Returns the raw bits, e.g., for use in `const` contexts.
+                pub const fn bits(self) -> u64 {
+                    self.0
+                }
+            }
+
             const _SIZE_CHECK: () = if std::mem::size_of::<u64>() != std::mem::size_of::<Flags>() {
                 panic!("KPF size > sizeof(u64)");
             } else { };
@@ -98,6 +120,16 @@ macro_rules! kpf {
                 fn values() -> &'static [Self] {
                     &[ $($name),* ]
                 }
+
+                fn name(self) -> Option<&'static str> {
+                    $(
+                        if self == $name {
+                            return Some(stringify!($name));
+                        }
+                    )+
+
+                    None
+                }
             }
 
             impl From<Flags> for u64 {
@@ -175,538 +207,143 @@ macro_rules! kpf {
 }
 
 /////////////////////////////////////////////////////////////////////////////////////////
-// Actual definitions of the different flags...
-
-// kpageflags for kernel 3.10.0
-kpf! {
-    KPF3_10_0 {
-        Locked = 0,
-        Error = 1,
-        Referenced = 2,
-        Uptodate = 3,
-        Dirty = 4,
-        Lru = 5,
-        Active = 6,
-        Slab = 7,
-        Writeback = 8,
-        Reclaim = 9,
-        Buddy = 10,
-        Mmap = 11,
-        Anon = 12,
-        Swapcache = 13,
-        Swapbacked = 14,
-        CompoundHead = 15,
-        CompoundTail = 16,
-        Huge = 17,
-        Unevictable = 18,
-        Hwpoison = 19,
-        Nopage = 20,
-        Ksm = 21,
-        Thp = 22,
-        Balloon = 23,
-        ZeroPage = 24,
-        Idle = 25,
-
-        Reserved = 32,
-        Mlocked = 33,
-        Mappedtodisk = 34,
-        Private = 35,
-        Private2 = 36,
-        OwnerPrivate = 37,
-        Arch = 38,
-        Uncached = 39,
-
-        Readahead = 48,
-        Slobfree = 49,
-        Slubfrozen = 50,
-        Slubdebug = 51,
-    }
+// Which kernels have which flags...
 
-    NOPAGE: Self = Nopage;
-    COMPOUND_HEAD: Self = CompoundHead;
-    COMPOUND_TAIL: Self = CompoundTail;
-    PGTABLE: Option<Self> = None;
-    BUDDY: Self = Buddy;
-    SLAB: Self = Slab;
-    RESERVED: Self = Reserved;
-    MMAP: Self = Mmap;
-    LRU: Self = Lru;
-    ANON: Self = Anon;
-    THP: Self = Thp;
-    PRIVATE: Self = Private;
-    PRIVATE2: Self = Private2;
-    OWNERPRIVATE1: Self = OwnerPrivate;
-}
+impl FlagSpec {
+    /// Returns the mask of this flag.
+    pub fn mask(&self) -> u64 {
+        1 << self.bit
+    }
 
-// kpageflags for kernel 4.15.0
-kpf! {
-    KPF4_15_0 {
-        Locked = 0,
-        Error = 1,
-        Referenced = 2,
-        Uptodate = 3,
-        Dirty = 4,
-        Lru = 5,
-        Active = 6,
-        Slab = 7,
-        Writeback = 8,
-        Reclaim = 9,
-        Buddy = 10,
-        Mmap = 11,
-        Anon = 12,
-        Swapcache = 13,
-        Swapbacked = 14,
-        CompoundHead = 15,
-        CompoundTail = 16,
-        Huge = 17,
-        Unevictable = 18,
-        Hwpoison = 19,
-        Nopage = 20,
-        Ksm = 21,
-        Thp = 22,
-        Balloon = 23,
-        ZeroPage = 24,
-        Idle = 25,
-
-        Reserved = 32,
-        Mlocked = 33,
-        Mappedtodisk = 34,
-        Private = 35,
-        Private2 = 36,
-        OwnerPrivate = 37,
-        Arch = 38,
-        Uncached = 39,
-        Softdirty = 40,
-
-        Readahead = 48,
-        Slobfree = 49,
-        Slubfrozen = 50,
-        Slubdebug = 51,
-
-        File = 61,
-        Swap = 62,
-        MmapExclusive = 63,
+    /// Returns the first kernel version with this flag at this bit.
+    pub fn since(&self) -> KernelVersion {
+        let (major, minor, patch) = self.since;
+        KernelVersion::new(major, minor, patch)
     }
 
-    NOPAGE: Self = Nopage;
-    COMPOUND_HEAD: Self = CompoundHead;
-    COMPOUND_TAIL: Self = CompoundTail;
-    PGTABLE: Option<Self> = None;
-    BUDDY: Self = Buddy;
-    SLAB: Self = Slab;
-    RESERVED: Self = Reserved;
-    MMAP: Self = Mmap;
-    LRU: Self = Lru;
-    ANON: Self = Anon;
-    THP: Self = Thp;
-    PRIVATE: Self = Private;
-    PRIVATE2: Self = Private2;
-    OWNERPRIVATE1: Self = OwnerPrivate;
-}
+    /// Returns the first kernel version without this flag again, if any.
+    pub fn until(&self) -> Option<KernelVersion> {
+        self.until
+            .map(|(major, minor, patch)| KernelVersion::new(major, minor, patch))
+    }
 
-// kpageflags for kernel 5.0.8
-kpf! {
-    KPF5_0_8 {
-        Locked = 0,
-        Error = 1,
-        Referenced = 2,
-        Uptodate = 3,
-        Dirty = 4,
-        Lru = 5,
-        Active = 6,
-        Slab = 7,
-        Writeback = 8,
-        Reclaim = 9,
-        Buddy = 10,
-        Mmap = 11,
-        Anon = 12,
-        Swapcache = 13,
-        Swapbacked = 14,
-        CompoundHead = 15,
-        CompoundTail = 16,
-        Huge = 17,
-        Unevictable = 18,
-        Hwpoison = 19,
-        Nopage = 20,
-        Ksm = 21,
-        Thp = 22,
-        Offline = 23,
-        ZeroPage = 24,
-        Idle = 25,
-        Pgtable = 26,
-
-        Reserved = 32,
-        Mlocked = 33,
-        Mappedtodisk = 34,
-        Private = 35,
-        Private2 = 36,
-        OwnerPrivate = 37,
-        Arch = 38,
-        Uncached = 39,
-        Softdirty = 40,
-
-        Readahead = 48,
-        Slobfree = 49,
-        Slubfrozen = 50,
-        Slubdebug = 51,
-
-        File = 61,
-        Swap = 62,
-        MmapExclusive = 63,
+    /// Returns the known layouts with this flag, oldest first.
+    pub fn layouts(&self) -> impl Iterator<Item = KernelLayout> + '_ {
+        KernelLayout::ALL
+            .iter()
+            .copied()
+            .filter(|layout| self.in_layout(layout_version(*layout)))
     }
+}
 
-    NOPAGE: Self = Nopage;
-    COMPOUND_HEAD: Self = CompoundHead;
-    COMPOUND_TAIL: Self = CompoundTail;
-    PGTABLE: Option<Self> = Some(Pgtable);
-    BUDDY: Self = Buddy;
-    SLAB: Self = Slab;
-    RESERVED: Self = Reserved;
-    MMAP: Self = Mmap;
-    LRU: Self = Lru;
-    ANON: Self = Anon;
-    THP: Self = Thp;
-    PRIVATE: Self = Private;
-    PRIVATE2: Self = Private2;
-    OWNERPRIVATE1: Self = OwnerPrivate;
+const fn layout_version(layout: KernelLayout) -> (u32, u32, u32) {
+    let version = layout.version();
+    (version.major, version.minor, version.patch)
 }
 
-// kpageflags for kernel 5.4.0
-kpf! {
-    KPF5_4_0 {
-        Locked = 0,
-        Error = 1,
-        Referenced = 2,
-        Uptodate = 3,
-        Dirty = 4,
-        Lru = 5,
-        Active = 6,
-        Slab = 7,
-        Writeback = 8,
-        Reclaim = 9,
-        Buddy = 10,
-        Mmap = 11,
-        Anon = 12,
-        Swapcache = 13,
-        Swapbacked = 14,
-        CompoundHead = 15,
-        CompoundTail = 16,
-        Huge = 17,
-        Unevictable = 18,
-        Hwpoison = 19,
-        Nopage = 20,
-        Ksm = 21,
-        Thp = 22,
-        Offline = 23,
-        ZeroPage = 24,
-        Idle = 25,
-        Pgtable = 26,
-
-        Reserved = 32,
-        Mlocked = 33,
-        Mappedtodisk = 34,
-        Private = 35,
-        Private2 = 36,
-        OwnerPrivate = 37,
-        Arch = 38,
-        Uncached = 39,
-        Softdirty = 40,
-
-        Readahead = 48,
-        Slobfree = 49,
-        Slubfrozen = 50,
-        Slubdebug = 51,
-
-        File = 61,
-        Swap = 62,
-        MmapExclusive = 63,
+// The table must have a layout for each `KernelLayout` and vice versa.
+const _LAYOUTS_CHECK: () = {
+    assert!(LAYOUTS.len() == KernelLayout::ALL.len());
+    let mut i = 0;
+    while i < LAYOUTS.len() {
+        let (major, minor, patch) = layout_version(KernelLayout::ALL[i]);
+        assert!(LAYOUTS[i].0 == major && LAYOUTS[i].1 == minor && LAYOUTS[i].2 == patch);
+        i += 1;
     }
+};
 
-    NOPAGE: Self = Nopage;
-    COMPOUND_HEAD: Self = CompoundHead;
-    COMPOUND_TAIL: Self = CompoundTail;
-    PGTABLE: Option<Self> = Some(Pgtable);
-    BUDDY: Self = Buddy;
-    SLAB: Self = Slab;
-    RESERVED: Self = Reserved;
-    MMAP: Self = Mmap;
-    LRU: Self = Lru;
-    ANON: Self = Anon;
-    THP: Self = Thp;
-    PRIVATE: Self = Private;
-    PRIVATE2: Self = Private2;
-    OWNERPRIVATE1: Self = OwnerPrivate;
+/// Returns every flag that any known kernel reports, in order of bit. A bit that was reused over
+/// time has one entry per meaning.
+pub fn flag_table() -> &'static [FlagSpec] {
+    FLAGS
 }
 
-// kpageflags for kernel 5.13.0
-kpf! {
-    KPF5_13_0 {
-        Locked = 0,
-        Error = 1,
-        Referenced = 2,
-        Uptodate = 3,
-        Dirty = 4,
-        Lru = 5,
-        Active = 6,
-        Slab = 7,
-        Writeback = 8,
-        Reclaim = 9,
-        Buddy = 10,
-        Mmap = 11,
-        Anon = 12,
-        Swapcache = 13,
-        Swapbacked = 14,
-        CompoundHead = 15,
-        CompoundTail = 16,
-        Huge = 17,
-        Unevictable = 18,
-        Hwpoison = 19,
-        Nopage = 20,
-        Ksm = 21,
-        Thp = 22,
-        Offline = 23,
-        ZeroPage = 24,
-        Idle = 25,
-        Pgtable = 26,
-
-        Reserved = 32,
-        Mlocked = 33,
-        Mappedtodisk = 34,
-        Private = 35,
-        Private2 = 36,
-        OwnerPrivate = 37,
-        Arch = 38,
-        Uncached = 39,
-        Softdirty = 40,
-        Arch2 = 41,
-
-        Readahead = 48,
-        Slobfree = 49,
-        Slubfrozen = 50,
-        Slubdebug = 51,
-
-        File = 61,
-        Swap = 62,
-        MmapExclusive = 63,
-    }
+/// Returns the flags of the given layout, in order of bit.
+pub fn layout_flags(layout: KernelLayout) -> impl Iterator<Item = &'static FlagSpec> {
+    FLAGS
+        .iter()
+        .filter(move |flag| flag.in_layout(layout_version(layout)))
+}
 
-    NOPAGE: Self = Nopage;
-    COMPOUND_HEAD: Self = CompoundHead;
-    COMPOUND_TAIL: Self = CompoundTail;
-    PGTABLE: Option<Self> = Some(Pgtable);
-    BUDDY: Self = Buddy;
-    SLAB: Self = Slab;
-    RESERVED: Self = Reserved;
-    MMAP: Self = Mmap;
-    LRU: Self = Lru;
-    ANON: Self = Anon;
-    THP: Self = Thp;
-    PRIVATE: Self = Private;
-    PRIVATE2: Self = Private2;
-    OWNERPRIVATE1: Self = OwnerPrivate;
+/// Returns the known layouts with the flag `name` (e.g., `"Offline"`) at bit `bit`, oldest first.
+pub fn layouts_with_flag(name: &str, bit: u32) -> Vec<KernelLayout> {
+    FLAGS
+        .iter()
+        .filter(|flag| flag.name == name && flag.bit == bit)
+        .flat_map(FlagSpec::layouts)
+        .collect()
 }
 
-// kpageflags for kernel 5.15.0
-kpf! {
-    KPF5_15_0 {
-        Locked = 0,
-        Error = 1,
-        Referenced = 2,
-        Uptodate = 3,
-        Dirty = 4,
-        Lru = 5,
-        Active = 6,
-        Slab = 7,
-        Writeback = 8,
-        Reclaim = 9,
-        Buddy = 10,
-        Mmap = 11,
-        Anon = 12,
-        Swapcache = 13,
-        Swapbacked = 14,
-        CompoundHead = 15,
-        CompoundTail = 16,
-        Huge = 17,
-        Unevictable = 18,
-        Hwpoison = 19,
-        Nopage = 20,
-        Ksm = 21,
-        Thp = 22,
-        Offline = 23,
-        ZeroPage = 24,
-        Idle = 25,
-        Pgtable = 26,
-
-        Reserved = 32,
-        Mlocked = 33,
-        Mappedtodisk = 34,
-        Private = 35,
-        Private2 = 36,
-        OwnerPrivate = 37,
-        Arch = 38,
-        Uncached = 39,
-        Softdirty = 40,
-        Arch2 = 41,
-
-        Readahead = 48,
-        Slobfree = 49,
-        Slubfrozen = 50,
-        Slubdebug = 51,
-
-        File = 61,
-        Swap = 62,
-        MmapExclusive = 63,
-    }
+/// Defines `CanonicalFlag`, with a variant for each flag of the `Canonical` layout.
+macro_rules! canonical_flag {
+    ($($name:ident = $val:literal),+ $(,)?) => {
+        /// A flag of any kernel, independent of its bit in any particular layout. The discriminant
+        /// is the bit of the flag in the `Canonical` layout.
+        #[derive(Copy, Clone, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
+        #[repr(u32)]
+        pub enum CanonicalFlag {
+            $($name = $val,)+
+        }
 
-    NOPAGE: Self = Nopage;
-    COMPOUND_HEAD: Self = CompoundHead;
-    COMPOUND_TAIL: Self = CompoundTail;
-    PGTABLE: Option<Self> = Some(Pgtable);
-    BUDDY: Self = Buddy;
-    SLAB: Self = Slab;
-    RESERVED: Self = Reserved;
-    MMAP: Self = Mmap;
-    LRU: Self = Lru;
-    ANON: Self = Anon;
-    THP: Self = Thp;
-    PRIVATE: Self = Private;
-    PRIVATE2: Self = Private2;
-    OWNERPRIVATE1: Self = OwnerPrivate;
-}
+        impl CanonicalFlag {
+            /// Every flag, in order of bit in the `Canonical` layout.
+            pub const ALL: &'static [CanonicalFlag] = &[ $(CanonicalFlag::$name),+ ];
 
-// kpageflags for kernel 5.17.0
-kpf! {
-    KPF5_17_0 {
-        Locked = 0,
-        Error = 1,
-        Referenced = 2,
-        Uptodate = 3,
-        Dirty = 4,
-        Lru = 5,
-        Active = 6,
-        Slab = 7,
-        Writeback = 8,
-        Reclaim = 9,
-        Buddy = 10,
-        Mmap = 11,
-        Anon = 12,
-        Swapcache = 13,
-        Swapbacked = 14,
-        CompoundHead = 15,
-        CompoundTail = 16,
-        Huge = 17,
-        Unevictable = 18,
-        Hwpoison = 19,
-        Nopage = 20,
-        Ksm = 21,
-        Thp = 22,
-        Offline = 23,
-        ZeroPage = 24,
-        Idle = 25,
-        Pgtable = 26,
-
-        Reserved = 32,
-        Mlocked = 33,
-        Mappedtodisk = 34,
-        Private = 35,
-        Private2 = 36,
-        OwnerPrivate = 37,
-        Arch = 38,
-        Uncached = 39,
-        Softdirty = 40,
-        Arch2 = 41,
-
-        Readahead = 48,
-        Slobfree = 49,
-        Slubfrozen = 50,
-        Slubdebug = 51,
-
-        File = 61,
-        Swap = 62,
-        MmapExclusive = 63,
-    }
+            /// Returns the name of the flag, as in the `KPF*` layouts.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(CanonicalFlag::$name => stringify!($name),)+
+                }
+            }
 
-    NOPAGE: Self = Nopage;
-    COMPOUND_HEAD: Self = CompoundHead;
-    COMPOUND_TAIL: Self = CompoundTail;
-    PGTABLE: Option<Self> = Some(Pgtable);
-    BUDDY: Self = Buddy;
-    SLAB: Self = Slab;
-    RESERVED: Self = Reserved;
-    MMAP: Self = Mmap;
-    LRU: Self = Lru;
-    ANON: Self = Anon;
-    THP: Self = Thp;
-    PRIVATE: Self = Private;
-    PRIVATE2: Self = Private2;
-    OWNERPRIVATE1: Self = OwnerPrivate;
-}
+            /// Returns the flag in the `Canonical` layout.
+            pub fn flags(self) -> Canonical::Flags {
+                Canonical::Flags::from(1 << self as u32)
+            }
 
-// kpageflags for kernel 6.0.0
-kpf! {
-    KPF6_0_0 {
-        Locked = 0,
-        Error = 1,
-        Referenced = 2,
-        Uptodate = 3,
-        Dirty = 4,
-        Lru = 5,
-        Active = 6,
-        Slab = 7,
-        Writeback = 8,
-        Reclaim = 9,
-        Buddy = 10,
-        Mmap = 11,
-        Anon = 12,
-        Swapcache = 13,
-        Swapbacked = 14,
-        CompoundHead = 15,
-        CompoundTail = 16,
-        Huge = 17,
-        Unevictable = 18,
-        Hwpoison = 19,
-        Nopage = 20,
-        Ksm = 21,
-        Thp = 22,
-        Offline = 23,
-        ZeroPage = 24,
-        Idle = 25,
-        Pgtable = 26,
-
-        Reserved = 32,
-        Mlocked = 33,
-        Mappedtodisk = 34,
-        Private = 35,
-        Private2 = 36,
-        OwnerPrivate = 37,
-        Arch = 38,
-        Uncached = 39,
-        Softdirty = 40,
-        Arch2 = 41,
-
-        AnonExclusive = 47,
-        Readahead = 48,
-        Slobfree = 49,
-        Slubfrozen = 50,
-        Slubdebug = 51,
-
-        File = 61,
-        Swap = 62,
-        MmapExclusive = 63,
-    }
+            /// Returns the canonical flag with the same name as `flag`, a single flag of any
+            /// layout, if any.
+            pub fn of<K: Flaggy>(flag: K) -> Option<Self> {
+                flag.name().and_then(|name| name.parse().ok())
+            }
+
+            /// Returns the flag with the same name in layout `K`, if `K` has it.
+            pub fn to_layout<K: Flaggy>(self) -> Option<K> {
+                self.name().parse().ok()
+            }
+        }
+
+        impl FromStr for CanonicalFlag {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    $(
+                        stringify!($name) => Ok(CanonicalFlag::$name),
+                    )+
 
-    NOPAGE: Self = Nopage;
-    COMPOUND_HEAD: Self = CompoundHead;
-    COMPOUND_TAIL: Self = CompoundTail;
-    PGTABLE: Option<Self> = Some(Pgtable);
-    BUDDY: Self = Buddy;
-    SLAB: Self = Slab;
-    RESERVED: Self = Reserved;
-    MMAP: Self = Mmap;
-    LRU: Self = Lru;
-    ANON: Self = Anon;
-    THP: Self = Thp;
-    PRIVATE: Self = Private;
-    PRIVATE2: Self = Private2;
-    OWNERPRIVATE1: Self = OwnerPrivate;
+                    other => Err(format!("unknown flag: {}", other)),
+                }
+            }
+        }
+
+        impl std::fmt::Display for CanonicalFlag {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.name())
+            }
+        }
+
+        impl From<CanonicalFlag> for Canonical::Flags {
+            fn from(flag: CanonicalFlag) -> Self {
+                flag.flags()
+            }
+        }
+    };
 }
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// Actual definitions of the different flags, generated by `build.rs` from `table.rs`. This
+// includes `Canonical`, a layout with the flags of every kernel (see `table::CANONICAL_BITS`),
+// and `CanonicalFlag`...
+
+include!(concat!(env!("OUT_DIR"), "/kpf.rs"));
@@ -0,0 +1,345 @@
+//! Reading the `KPF_*` defines from a kernel's sources, to add layouts for new kernels.
+//!
+//! The public bits are in `include/uapi/linux/kernel-page-flags.h`. The kernel-internal ones are
+//! in `include/linux/kernel-page-flags.h` (or `fs/proc/page.c` on older kernels), and the
+//! overloaded ones only exist in `tools/mm/page-types.c`.
+
+use std::io::{self, BufRead};
+
+use crate::kernel::{KernelLayout, KernelVersion};
+
+use super::{flag_table, layout_flags, table, FlagSpec};
+
+/// A `#define KPF_<NAME> <BIT>` from a kernel source file.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct KpfDefine {
+    /// The name without the `KPF_` prefix, e.g., `COMPOUND_HEAD`.
+    pub name: String,
+    pub bit: u32,
+}
+
+impl KpfDefine {
+    /// Returns the name of the flag in the `KPF*` layouts, e.g., `CompoundHead` for
+    /// `KPF_COMPOUND_HEAD`. Flags that we already know keep their existing names, even where
+    /// those don't follow the pattern (e.g., `Slobfree` for `KPF_SLOB_FREE`).
+    pub fn flag_name(&self) -> String {
+        let squashed = self.name.replace('_', "");
+        if let Some(known) = flag_table()
+            .iter()
+            .find(|flag| flag.name.eq_ignore_ascii_case(&squashed))
+        {
+            return known.name.to_owned();
+        }
+
+        self.name
+            .split('_')
+            .map(|word| {
+                let mut chars = word.chars();
+                chars.next().map_or(String::new(), |first| {
+                    first.to_ascii_uppercase().to_string() + &chars.as_str().to_ascii_lowercase()
+                })
+            })
+            .collect()
+    }
+}
+
+/// `KPF_*` defines that are not flags, e.g., `KPF_BYTES` in `page-types.c`.
+const NOT_FLAGS: &[&str] = &["BYTES"];
+
+/// Parses the `#define KPF_* <bit>` lines of a kernel source file, ignoring everything else
+/// (including defines whose values aren't plain bit numbers, like `KPF_ALL_BITS`).
+pub fn parse_kpf_defines<R: BufRead>(reader: R) -> io::Result<Vec<KpfDefine>> {
+    let mut defines = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        if words.next() != Some("#define") {
+            continue;
+        }
+        let (Some(name), Some(value)) = (words.next(), words.next()) else {
+            continue;
+        };
+        let Some(name) = name.strip_prefix("KPF_") else {
+            continue;
+        };
+        if NOT_FLAGS.contains(&name) {
+            continue;
+        }
+
+        if let Ok(bit @ 0..=63) = value.parse::<u32>() {
+            defines.push(KpfDefine {
+                name: name.to_owned(),
+                bit,
+            });
+        }
+    }
+
+    Ok(defines)
+}
+
+/// Combines the defines of several files into one layout, sorted by bit. Defines repeated with the
+/// same bit (e.g., in both a header and `page-types.c`) are merged.
+///
+/// Returns an error if a flag is defined at two different bits, or two flags at the same bit.
+pub fn merge_kpf_defines(
+    files: impl IntoIterator<Item = Vec<KpfDefine>>,
+) -> Result<Vec<KpfDefine>, String> {
+    let mut merged: Vec<KpfDefine> = Vec::new();
+
+    for define in files.into_iter().flatten() {
+        match merged
+            .iter()
+            .find(|other| other.name == define.name || other.bit == define.bit)
+        {
+            None => merged.push(define),
+            Some(other) if *other == define => {}
+            Some(other) => {
+                return Err(format!(
+                    "KPF_{} = {} conflicts with KPF_{} = {}",
+                    define.name, define.bit, other.name, other.bit
+                ))
+            }
+        }
+    }
+    merged.sort_by_key(|define| define.bit);
+
+    Ok(merged)
+}
+
+/// The edits to `table.rs` that make its layout for kernel `version` match the kernel's `KPF_*`
+/// defines, relative to the nearest layout that is not newer (see `KernelLayout::nearest`).
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct TableEdits {
+    pub version: KernelVersion,
+    /// Whether `LAYOUTS` needs a row for `version`, i.e., there is no layout for it yet.
+    pub new_layout: bool,
+    /// Flags to add to `FLAGS`, since `version`, as the flag name and bit.
+    pub added: Vec<(String, u32)>,
+    /// Entries of `FLAGS` that need to end at `version`, because the kernel doesn't have them.
+    pub ended: Vec<&'static FlagSpec>,
+}
+
+impl TableEdits {
+    /// Compares `defines` (see `merge_kpf_defines`) with the table.
+    ///
+    /// Returns an error if `version` is older than every layout, or if the edited layout would be
+    /// invalid, e.g., because it lacks a flag that `Flaggy` needs.
+    pub fn new(version: KernelVersion, defines: &[KpfDefine]) -> Result<Self, String> {
+        let base = KernelLayout::nearest(version);
+        if base.version() > version {
+            return Err(format!("kernel {version} is older than every layout"));
+        }
+
+        let upstream: Vec<_> = defines
+            .iter()
+            .map(|define| (define.flag_name(), define.bit))
+            .collect();
+
+        let added: Vec<_> = upstream
+            .iter()
+            .filter(|(name, bit)| {
+                !layout_flags(base).any(|flag| flag.name == name && flag.bit == *bit)
+            })
+            .cloned()
+            .collect();
+        let ended: Vec<_> = layout_flags(base)
+            .filter(|flag| {
+                !upstream
+                    .iter()
+                    .any(|(name, bit)| flag.name == name && flag.bit == *bit)
+            })
+            .collect();
+
+        let flags: Vec<_> = upstream
+            .iter()
+            .map(|(name, bit)| (name.as_str(), *bit))
+            .collect();
+        table::kpf_block(&format!("the layout of {version}"), &flags)?;
+
+        Ok(TableEdits {
+            version,
+            new_layout: base.version() != version,
+            added,
+            ended,
+        })
+    }
+
+    /// Returns `true` if the table already matches the kernel.
+    pub fn is_empty(&self) -> bool {
+        !self.new_layout && self.added.is_empty() && self.ended.is_empty()
+    }
+}
+
+/// Renders the edits as lines to paste into `table.rs`, e.g., for `kpf-gen`.
+impl std::fmt::Display for TableEdits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let KernelVersion {
+            major,
+            minor,
+            patch,
+        } = self.version;
+        let version = format!("({major}, {minor}, {patch})");
+        let spec = |f: &mut std::fmt::Formatter<'_>, name: &str, bit, since: &str, until: &str| {
+            writeln!(
+                f,
+                "    flag({:<17}{:<4}{:<12}{}),",
+                format!("\"{name}\","),
+                format!("{bit},"),
+                format!("{since},"),
+                until
+            )
+        };
+
+        if self.new_layout {
+            writeln!(
+                f,
+                "// Add to `LAYOUTS`, and add `KernelLayout::V{major}_{minor}_{patch}`:"
+            )?;
+            writeln!(f, "    {version},")?;
+        }
+        if !self.added.is_empty() {
+            writeln!(f, "// Add to `FLAGS`:")?;
+            for (name, bit) in self.added.iter() {
+                spec(f, name, bit, &version, "None")?;
+            }
+        }
+        if !self.ended.is_empty() {
+            writeln!(f, "// End in `FLAGS`:")?;
+            for flag in self.ended.iter() {
+                let since = flag.since();
+                let since = format!("({}, {}, {})", since.major, since.minor, since.patch);
+                spec(f, flag.name, &flag.bit, &since, &format!("Some({version})"))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::KernelVersion;
+
+    /// The `KPF_*` sources of each layout, from `testdata/kernel`.
+    const SOURCES: &[(KernelVersion, &[&str])] = &[
+        (
+            KernelVersion::new(5, 19, 0),
+            &[
+                include_str!("../../testdata/kernel/5.19/include/uapi/linux/kernel-page-flags.h"),
+                include_str!("../../testdata/kernel/5.19/include/linux/kernel-page-flags.h"),
+                include_str!("../../testdata/kernel/5.19/tools/vm/page-types.c"),
+            ],
+        ),
+        (
+            KernelVersion::new(6, 1, 0),
+            &[
+                include_str!("../../testdata/kernel/6.1/include/uapi/linux/kernel-page-flags.h"),
+                include_str!("../../testdata/kernel/6.1/include/linux/kernel-page-flags.h"),
+                include_str!("../../testdata/kernel/6.1/tools/vm/page-types.c"),
+            ],
+        ),
+        (
+            KernelVersion::new(6, 6, 0),
+            &[
+                include_str!("../../testdata/kernel/6.6/include/uapi/linux/kernel-page-flags.h"),
+                include_str!("../../testdata/kernel/6.6/include/linux/kernel-page-flags.h"),
+                include_str!("../../testdata/kernel/6.6/tools/mm/page-types.c"),
+            ],
+        ),
+        (
+            KernelVersion::new(6, 12, 0),
+            &[
+                include_str!("../../testdata/kernel/6.12/include/uapi/linux/kernel-page-flags.h"),
+                include_str!("../../testdata/kernel/6.12/include/linux/kernel-page-flags.h"),
+                include_str!("../../testdata/kernel/6.12/tools/mm/page-types.c"),
+            ],
+        ),
+    ];
+
+    fn defines(sources: &[&str]) -> Vec<KpfDefine> {
+        let files = sources
+            .iter()
+            .map(|source| parse_kpf_defines(source.as_bytes()).unwrap());
+        merge_kpf_defines(files).unwrap()
+    }
+
+    #[test]
+    fn layouts_match_kernel_sources() {
+        for (version, sources) in SOURCES {
+            let edits = TableEdits::new(*version, &defines(sources)).unwrap();
+            assert!(edits.is_empty(), "layout of {}:\n{}", version, edits);
+        }
+    }
+
+    #[test]
+    fn new_kernels_need_edits() {
+        let (_, sources) = SOURCES[SOURCES.len() - 1];
+        let mut defines = defines(sources);
+        defines.retain(|define| define.name != "SLUB_DEBUG");
+        defines.push(KpfDefine {
+            name: "NEW_FLAG".to_owned(),
+            bit: 43,
+        });
+
+        let edits = TableEdits::new(KernelVersion::new(6, 18, 0), &defines).unwrap();
+        assert!(edits.new_layout);
+        assert_eq!(edits.added, [("NewFlag".to_owned(), 43)]);
+        let ended: Vec<_> = edits.ended.iter().map(|flag| flag.name).collect();
+        assert_eq!(ended, ["Slubdebug"]);
+        assert_eq!(
+            edits.to_string(),
+            "\
+// Add to `LAYOUTS`, and add `KernelLayout::V6_18_0`:
+    (6, 18, 0),
+// Add to `FLAGS`:
+    flag(\"NewFlag\",       43, (6, 18, 0), None),
+// End in `FLAGS`:
+    flag(\"Slubdebug\",     51, (3, 10, 0), Some((6, 18, 0))),
+"
+        );
+
+        defines.retain(|define| define.name != "BUDDY");
+        assert!(TableEdits::new(KernelVersion::new(6, 18, 0), &defines).is_err());
+    }
+
+    #[test]
+    fn defines_are_parsed() {
+        let source = "\
+#define KPF_LOCKED\t\t0
+#define KPF_BYTES\t\t8
+#define KPF_SLOB_FREE\t\t49
+#define KPF_ALL_BITS\t\t((uint64_t)~0ULL)
+#define KPF_TOO_BIG\t\t64
+ #define NOT_KPF 3
+";
+        let defines = parse_kpf_defines(source.as_bytes()).unwrap();
+        let names: Vec<_> = defines
+            .iter()
+            .map(|define| (define.flag_name(), define.bit))
+            .collect();
+        assert_eq!(
+            names,
+            [("Locked".to_owned(), 0), ("Slobfree".to_owned(), 49)]
+        );
+    }
+
+    #[test]
+    fn conflicting_defines_are_rejected() {
+        let define = |name: &str, bit| KpfDefine {
+            name: name.to_owned(),
+            bit,
+        };
+
+        let merged = merge_kpf_defines([
+            vec![define("LOCKED", 0), define("BUDDY", 10)],
+            vec![define("LOCKED", 0)],
+        ])
+        .unwrap();
+        assert_eq!(merged.len(), 2);
+
+        assert!(merge_kpf_defines([vec![define("LOCKED", 0)], vec![define("LOCKED", 1)]]).is_err());
+        assert!(merge_kpf_defines([vec![define("LOCKED", 0)], vec![define("ERROR", 0)]]).is_err());
+    }
+}
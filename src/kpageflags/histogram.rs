@@ -0,0 +1,294 @@
+//! Counting pages per distinct combination of flags, similar to the summary of `page-types`.
+
+use std::collections::HashMap;
+
+use super::{flags::Flaggy, translate::Translation, KPageFlags};
+
+/// Formats a number of bytes in human-readable binary units, e.g., `1.5 GiB`.
+pub(crate) fn human_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Counts pages per distinct combination of flags.
+///
+/// The `Display` impl renders a table with the count and total size of each combination, most
+/// common first.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FlagHistogram<K: Flaggy> {
+    counts: HashMap<KPageFlags<K>, u64>,
+    /// Flags cleared from every page before counting.
+    ignored_flags: K,
+    page_size: u64,
+}
+
+impl<K: Flaggy> FlagHistogram<K> {
+    pub fn new() -> Self {
+        Self::with_ignored_flags(&[])
+    }
+
+    /// Creates a histogram that clears the given flags from every page before counting it, like
+    /// the `ignored_flags` of `KPageFlagsIterator`.
+    pub fn with_ignored_flags(ignored_flags: &[K]) -> Self {
+        FlagHistogram {
+            counts: HashMap::new(),
+            ignored_flags: ignored_flags.iter().fold(K::empty(), |a, b| a | *b),
+            page_size: crate::page_size() as u64,
+        }
+    }
+
+    /// Sets the page size used to render sizes. Defaults to the page size of this system.
+    pub fn with_page_size(mut self, page_size: u64) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Counts `npages` pages with the given flags.
+    pub fn add_n(&mut self, mut flags: KPageFlags<K>, npages: u64) {
+        flags.clear(self.ignored_flags);
+        *self.counts.entry(flags).or_insert(0) += npages;
+    }
+
+    /// Counts a single page with the given flags.
+    pub fn add(&mut self, flags: KPageFlags<K>) {
+        self.add_n(flags, 1);
+    }
+
+    /// Adds all counts from `other` to `self`, clearing the flags ignored by `self` from them.
+    pub fn merge(&mut self, other: &Self) {
+        for (flags, count) in other.counts.iter() {
+            self.add_n(*flags, *count);
+        }
+    }
+
+    /// Translates the histogram to layout `T`, e.g., to merge it with histograms from other
+    /// kernels. Combinations that become the same are counted together. Also returns the flags
+    /// that were dropped from any page because `T` doesn't have them.
+    pub fn translate<T: Flaggy>(&self, translation: &Translation<K, T>) -> (FlagHistogram<T>, K) {
+        let ignored_flags = translation.translate(KPageFlags(self.ignored_flags)).0;
+        let mut translated = FlagHistogram {
+            counts: HashMap::new(),
+            ignored_flags: ignored_flags.0,
+            page_size: self.page_size,
+        };
+
+        let mut dropped = K::empty();
+        for (flags, count) in self.counts.iter() {
+            let (flags, lost) = translation.translate(*flags);
+            translated.add_n(flags, *count);
+            dropped |= lost;
+        }
+
+        (translated, dropped)
+    }
+
+    /// Returns the number of pages with exactly the given flags.
+    pub fn get(&self, flags: KPageFlags<K>) -> u64 {
+        self.counts.get(&flags).copied().unwrap_or(0)
+    }
+
+    /// Returns the total number of pages counted.
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// Returns the number of distinct combinations of flags.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Iterates over the combinations of flags and their counts in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (KPageFlags<K>, u64)> + '_ {
+        self.counts.iter().map(|(flags, count)| (*flags, *count))
+    }
+
+    /// Returns the combinations of flags and their counts, most common first. Ties are broken
+    /// by flags.
+    pub fn sorted_by_count(&self) -> Vec<(KPageFlags<K>, u64)> {
+        let mut sorted: Vec<_> = self.iter().collect();
+        sorted.sort_by(|(fa, ca), (fb, cb)| cb.cmp(ca).then(fa.cmp(fb)));
+        sorted
+    }
+}
+
+impl<K: Flaggy> Default for FlagHistogram<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Flaggy> Extend<KPageFlags<K>> for FlagHistogram<K> {
+    fn extend<I: IntoIterator<Item = KPageFlags<K>>>(&mut self, iter: I) {
+        for flags in iter {
+            self.add(flags);
+        }
+    }
+}
+
+impl<K: Flaggy> FromIterator<KPageFlags<K>> for FlagHistogram<K> {
+    fn from_iter<I: IntoIterator<Item = KPageFlags<K>>>(iter: I) -> Self {
+        let mut histogram = Self::new();
+        histogram.extend(iter);
+        histogram
+    }
+}
+
+impl<K: Flaggy> std::fmt::Display for FlagHistogram<K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:>18} {:>12} {:>10}  symbolic-flags",
+            "flags", "page-count", "size"
+        )?;
+
+        for (flags, count) in self.sorted_by_count() {
+            writeln!(
+                f,
+                "0x{:016x} {:>12} {:>10}  {}",
+                flags.as_u64(),
+                count,
+                human_size(count * self.page_size),
+                flags
+                    .to_string()
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            )?;
+        }
+
+        let total = self.total();
+        writeln!(
+            f,
+            "{:>18} {:>12} {:>10}",
+            "total",
+            total,
+            human_size(total * self.page_size)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kpageflags::{Canonical, KPF3_10_0, KPF6_0_0};
+
+    type Flags = KPF6_0_0::Flags;
+
+    fn flags(flags: Flags) -> KPageFlags<Flags> {
+        KPageFlags::from(flags)
+    }
+
+    #[test]
+    fn ignored_flags_are_cleared() {
+        let mut histogram = FlagHistogram::with_ignored_flags(&[KPF6_0_0::Referenced]);
+        histogram.add(flags(KPF6_0_0::Lru | KPF6_0_0::Referenced));
+        histogram.add_n(flags(KPF6_0_0::Lru), 2);
+
+        assert_eq!(histogram.len(), 1);
+        assert_eq!(histogram.get(flags(KPF6_0_0::Lru)), 3);
+        assert_eq!(
+            histogram.get(flags(KPF6_0_0::Lru | KPF6_0_0::Referenced)),
+            0
+        );
+    }
+
+    #[test]
+    fn merge_uses_own_ignored_flags() {
+        let mut histogram = FlagHistogram::with_ignored_flags(&[KPF6_0_0::Referenced]);
+        histogram.add(flags(KPF6_0_0::Lru));
+
+        let mut other = FlagHistogram::with_ignored_flags(&[KPF6_0_0::Idle]);
+        other.add_n(flags(KPF6_0_0::Lru | KPF6_0_0::Referenced), 2);
+        other.add_n(flags(KPF6_0_0::Buddy | KPF6_0_0::Idle), 4);
+
+        histogram.merge(&other);
+        assert_eq!(histogram.total(), 7);
+        assert_eq!(histogram.get(flags(KPF6_0_0::Lru)), 3);
+        assert_eq!(histogram.get(flags(KPF6_0_0::Buddy)), 4);
+    }
+
+    #[test]
+    fn translated_combinations_are_merged() {
+        let mut histogram = FlagHistogram::<Flags>::new();
+        histogram.add_n(flags(KPF6_0_0::Lru), 1);
+        histogram.add_n(flags(KPF6_0_0::Lru | KPF6_0_0::Offline), 2);
+        histogram.add_n(flags(KPF6_0_0::Buddy), 4);
+
+        let (old, dropped) = histogram.translate(&Translation::<Flags, KPF3_10_0::Flags>::new());
+        assert_eq!(dropped, KPF6_0_0::Offline);
+        assert_eq!(old.get(KPageFlags::from(KPF3_10_0::Lru)), 3);
+        assert_eq!(old.get(KPageFlags::from(KPF3_10_0::Buddy)), 4);
+        assert_eq!(old.total(), histogram.total());
+
+        let (canonical, dropped) =
+            histogram.translate(&Translation::<Flags, Canonical::Flags>::new());
+        assert_eq!(dropped, Flags::empty());
+        assert_eq!(canonical.len(), 3);
+    }
+
+    #[test]
+    fn ties_are_sorted_by_flags() {
+        let mut histogram = FlagHistogram::<Flags>::new();
+        histogram.add_n(flags(KPF6_0_0::Slab), 2);
+        histogram.add_n(flags(KPF6_0_0::Buddy), 5);
+        histogram.add_n(flags(KPF6_0_0::Lru), 2);
+
+        let sorted: Vec<_> = histogram
+            .sorted_by_count()
+            .into_iter()
+            .map(|(flags, count)| (flags.as_u64(), count))
+            .collect();
+        assert_eq!(
+            sorted,
+            [
+                (u64::from(KPF6_0_0::Buddy), 5),
+                (u64::from(KPF6_0_0::Lru), 2),
+                (u64::from(KPF6_0_0::Slab), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn table_is_rendered() {
+        let mut histogram = FlagHistogram::<Flags>::new().with_page_size(4096);
+        histogram.add_n(flags(KPF6_0_0::Lru | KPF6_0_0::Anon), 3);
+        histogram.add_n(flags(Flags::empty()), 1);
+
+        let table = histogram.to_string();
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "             flags   page-count       size  symbolic-flags",
+                "0x0000000000001020            3   12.0 KiB  Lru Anon",
+                "0x0000000000000000            1    4.0 KiB  ",
+                "             total            4   16.0 KiB",
+            ]
+        );
+    }
+
+    #[test]
+    fn sizes_are_human_readable() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1024), "1.0 KiB");
+        assert_eq!(human_size(1536 << 20), "1.5 GiB");
+        assert_eq!(human_size(u64::MAX), "16.0 EiB");
+    }
+}
@@ -0,0 +1,291 @@
+//! Scanning all of physical memory in parallel with `rayon`. Requires the `rayon` feature.
+
+use std::{io, ops::Range};
+
+use rayon::prelude::*;
+
+use crate::{zeroed_buf, Error};
+
+use super::{
+    flags::Flaggy,
+    histogram::FlagHistogram,
+    read::KPageFlagsFile,
+    regions::{CombinePolicy, DefaultCombine, KPageFlagsRegions, Region},
+    KPageFlags,
+};
+
+/// The default number of pages per chunk: 2 MiB worth of records.
+const DEFAULT_CHUNK_PAGES: u64 = (1 << 21) / std::mem::size_of::<u64>() as u64;
+
+/// Where the records of a `ParallelScan` come from.
+enum Source<'a, K: Flaggy> {
+    /// Read each chunk with positioned reads.
+    File(&'a KPageFlagsFile<K>),
+    /// Records already in memory (e.g., an mmapped dump), starting at the given PFN.
+    Slice(&'a [KPageFlags<K>], u64),
+}
+
+/// Splits a range of PFNs into chunks, and folds over them in parallel.
+///
+/// Results are combined in PFN order, so they are identical to those of a sequential scan with
+/// `KPageFlagsIterator` as long as the combining operation is associative.
+pub struct ParallelScan<'a, K: Flaggy> {
+    source: Source<'a, K>,
+    range: Range<u64>,
+    chunk_pages: u64,
+}
+
+impl<'a, K: Flaggy + Send + Sync> ParallelScan<'a, K> {
+    /// Scans all records of the file, e.g., `KPageFlagsFile::open(KPAGEFLAGS_PATH)`.
+    pub fn file(file: &'a KPageFlagsFile<K>) -> io::Result<Self> {
+        Ok(ParallelScan {
+            range: 0..file.record_count()?,
+            source: Source::File(file),
+            chunk_pages: DEFAULT_CHUNK_PAGES,
+        })
+    }
+
+    /// Scans records already in memory, where `records[0]` is the page at PFN `start_pfn`. With
+    /// the `mmap` feature, this can be a `KPageFlagsMmap` or a section of a snapshot.
+    pub fn slice(records: &'a [KPageFlags<K>], start_pfn: u64) -> Self {
+        ParallelScan {
+            range: start_pfn..start_pfn + records.len() as u64,
+            source: Source::Slice(records, start_pfn),
+            chunk_pages: DEFAULT_CHUNK_PAGES,
+        }
+    }
+
+    /// Restricts the scan to the given range of PFNs.
+    pub fn range(mut self, range: Range<u64>) -> Self {
+        self.range = range.start.max(self.range.start)..range.end.min(self.range.end);
+        self
+    }
+
+    /// Sets the number of pages in each chunk.
+    pub fn chunk_pages(mut self, chunk_pages: u64) -> Self {
+        assert!(chunk_pages > 0);
+        self.chunk_pages = chunk_pages;
+        self
+    }
+
+    /// Calls `map` on each chunk with the PFN of its first page and its flags, and then combines
+    /// the results with `reduce` in PFN order. Returns `None` if the range is empty.
+    pub fn map_reduce<T, M, R>(&self, map: M, reduce: R) -> Result<Option<T>, Error>
+    where
+        T: Send,
+        M: Fn(u64, &[KPageFlags<K>]) -> T + Sync,
+        R: Fn(T, T) -> T + Sync,
+    {
+        let nchunks = (self.range.end.saturating_sub(self.range.start)).div_ceil(self.chunk_pages);
+
+        (0..nchunks)
+            .into_par_iter()
+            .map(|i| -> Result<T, Error> {
+                let start = self.range.start + i * self.chunk_pages;
+                let end = (start + self.chunk_pages).min(self.range.end);
+
+                match self.source {
+                    Source::File(file) => {
+                        let mut buf = zeroed_buf((end - start) as usize);
+                        let n = file.read_at(start, &mut buf)?;
+                        Ok(map(start, &buf[..n]))
+                    }
+                    Source::Slice(records, base) => Ok(map(
+                        start,
+                        &records[(start - base) as usize..(end - base) as usize],
+                    )),
+                }
+            })
+            .try_reduce_with(|a, b| Ok(reduce(a, b)))
+            .transpose()
+    }
+
+    /// Counts pages per combination of flags, like `FlagHistogram::with_ignored_flags`.
+    pub fn histogram(&self, ignored_flags: &[K]) -> Result<FlagHistogram<K>, Error> {
+        let histogram = self.map_reduce(
+            |_, chunk| {
+                let mut histogram = FlagHistogram::with_ignored_flags(ignored_flags);
+                histogram.extend(chunk.iter().copied());
+                histogram
+            },
+            |mut a, b| {
+                a.merge(&b);
+                a
+            },
+        )?;
+
+        Ok(histogram.unwrap_or_else(|| FlagHistogram::with_ignored_flags(ignored_flags)))
+    }
+
+    /// Coalesces pages into regions with the `DefaultCombine` policy, like `KPageFlagsRegions`.
+    pub fn regions(&self) -> Result<Vec<Region<K>>, Error> {
+        self.regions_with_policy(&DefaultCombine)
+    }
+
+    /// Coalesces pages into regions with the given policy, like
+    /// `KPageFlagsRegions::with_policy`.
+    pub fn regions_with_policy<P>(&self, policy: &P) -> Result<Vec<Region<K>>, Error>
+    where
+        P: CombinePolicy<K> + Sync,
+    {
+        /// The regions of some consecutive chunks, with the flags of their first and last pages.
+        struct Partial<K: Flaggy> {
+            regions: Vec<Region<K>>,
+            first: KPageFlags<K>,
+            last: KPageFlags<K>,
+        }
+
+        /// Lets each chunk use the same policy.
+        struct ByRef<'p, P>(&'p P);
+
+        impl<K: Flaggy, P: CombinePolicy<K>> CombinePolicy<K> for ByRef<'_, P> {
+            fn can_combine(&self, first: KPageFlags<K>, second: KPageFlags<K>) -> bool {
+                self.0.can_combine(first, second)
+            }
+        }
+
+        let partial = self.map_reduce(
+            |start, chunk| Partial {
+                regions: KPageFlagsRegions::with_policy(
+                    chunk.iter().copied(),
+                    start,
+                    ByRef(policy),
+                )
+                .collect(),
+                first: chunk.first().copied().unwrap_or(KPageFlags::empty()),
+                last: chunk.last().copied().unwrap_or(KPageFlags::empty()),
+            },
+            |mut a, b| {
+                // Chunks are only empty past the end of the file.
+                if b.regions.is_empty() {
+                    return a;
+                } else if a.regions.is_empty() {
+                    return b;
+                }
+
+                // Stitch together the regions at the boundary, exactly as a sequential scan would.
+                let mut rest = b.regions.into_iter();
+                if policy.can_combine(a.last, b.first) {
+                    a.regions.last_mut().unwrap().npages += rest.next().unwrap().npages;
+                }
+                a.regions.extend(rest);
+                a.last = b.last;
+                a
+            },
+        )?;
+
+        Ok(partial.map_or(Vec::new(), |partial| partial.regions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Write};
+
+    use super::*;
+    use crate::kpageflags::{KPageFlagsIterator, KPageFlagsReader, KPF6_0_0};
+
+    type Flags = KPF6_0_0::Flags;
+
+    /// Runs of identical pages and compound pages of pseudo-random lengths, so that regions
+    /// straddle chunk boundaries for any chunk size.
+    fn synthetic_flags() -> Vec<u64> {
+        let kinds = [
+            u64::from(KPF6_0_0::Buddy),
+            u64::from(KPF6_0_0::Lru | KPF6_0_0::Anon),
+            u64::from(KPF6_0_0::Slab),
+            0,
+        ];
+
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move |n: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % n
+        };
+
+        let mut flags = Vec::new();
+        while flags.len() < 5000 {
+            let len = 1 + next(40);
+            if next(4) == 0 {
+                let thp = u64::from(KPF6_0_0::Thp | KPF6_0_0::Anon);
+                flags.push(u64::from(KPF6_0_0::CompoundHead) | thp);
+                flags.extend((1..len).map(|_| u64::from(KPF6_0_0::CompoundTail) | thp));
+            } else {
+                let kind = kinds[next(kinds.len() as u64) as usize];
+                flags.extend((0..len).map(|_| kind));
+            }
+        }
+        flags
+    }
+
+    fn sequential(bytes: &[u8]) -> Vec<KPageFlags<Flags>> {
+        let reader = KPageFlagsReader::<_, Flags>::new(BufReader::new(bytes));
+        KPageFlagsIterator::new(reader, &[])
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn matches_sequential_scan() {
+        let bytes: Vec<u8> = synthetic_flags()
+            .into_iter()
+            .flat_map(u64::to_ne_bytes)
+            .collect();
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&bytes).unwrap();
+        let file = KPageFlagsFile::<Flags>::new(file);
+
+        let records = sequential(&bytes);
+        let start_pfn = 0x1000;
+        let histogram: FlagHistogram<Flags> = records.iter().copied().collect();
+        let regions: Vec<_> = KPageFlagsRegions::new(records.iter().copied(), start_pfn).collect();
+        let identical = |a: KPageFlags<Flags>, b: KPageFlags<Flags>| a == b;
+        let identical_regions: Vec<_> =
+            KPageFlagsRegions::with_policy(records.iter().copied(), start_pfn, identical).collect();
+
+        for chunk_pages in [1, 3, 7, 64, 1000, 100_000] {
+            let scan = ParallelScan::file(&file).unwrap().chunk_pages(chunk_pages);
+            assert_eq!(scan.histogram(&[]).unwrap(), histogram);
+            let file_regions: Vec<_> = scan
+                .regions()
+                .unwrap()
+                .into_iter()
+                .map(|region| Region {
+                    start_pfn: region.start_pfn + start_pfn,
+                    ..region
+                })
+                .collect();
+            assert_eq!(file_regions, regions, "chunk_pages = {}", chunk_pages);
+
+            let scan = ParallelScan::slice(&records, start_pfn).chunk_pages(chunk_pages);
+            assert_eq!(scan.histogram(&[]).unwrap(), histogram);
+            assert_eq!(scan.regions().unwrap(), regions);
+            assert_eq!(
+                scan.regions_with_policy(&identical).unwrap(),
+                identical_regions
+            );
+        }
+    }
+
+    #[test]
+    fn matches_sequential_scan_of_a_range() {
+        let bytes: Vec<u8> = synthetic_flags()
+            .into_iter()
+            .flat_map(u64::to_ne_bytes)
+            .collect();
+        let records = sequential(&bytes);
+        let range = 1234..4321;
+
+        let part = &records[range.start as usize..range.end as usize];
+        let histogram: FlagHistogram<Flags> = part.iter().copied().collect();
+        let regions: Vec<_> = KPageFlagsRegions::new(part.iter().copied(), range.start).collect();
+
+        let scan = ParallelScan::slice(&records, 0)
+            .range(range)
+            .chunk_pages(17);
+        assert_eq!(scan.histogram(&[]).unwrap(), histogram);
+        assert_eq!(scan.regions().unwrap(), regions);
+    }
+}
@@ -2,24 +2,25 @@
 
 use std::io::Read;
 
-use crate::FileReadableReader;
+use crate::{Error, FileReadableFile, FileReadableIterator, FileReadableReader, Unwrapped};
 
 use super::{flags::Flaggy, KPageFlags};
 
 /// Wrapper around a `Read` type that for the `/proc/kpageflags` file.
 pub type KPageFlagsReader<R, K> = FileReadableReader<R, KPageFlags<K>>;
 
+/// Random-access reader for the `/proc/kpageflags` file, indexed by PFN, e.g.,
+/// `KPageFlagsFile::open(KPAGEFLAGS_PATH)`. Use `iter_from` to scan from an arbitrary PFN.
+pub type KPageFlagsFile<K> = FileReadableFile<KPageFlags<K>>;
+
+/// A memory-mapped dump of `/proc/kpageflags`, which derefs to `&[KPageFlags<K>]`.
+#[cfg(feature = "mmap")]
+pub type KPageFlagsMmap<K> = crate::mmap::FileReadableMmap<KPageFlags<K>>;
+
 /// Turns a `KPageFlagsReader` into a proper (efficient) iterator over flags.
 pub struct KPageFlagsIterator<R: Read, K: Flaggy> {
-    /// The reader we are reading from.
-    reader: KPageFlagsReader<R, K>,
-
-    /// Temporary buffer for data read but not consumed yet.
-    buf: [KPageFlags<K>; 1 << (21 - 3)],
-    /// The number of valid flags in the buffer.
-    nflags: usize,
-    /// The index of the first valid, unconsumed flag in the buffer, if `nflags > 0`.
-    idx: usize,
+    /// The underlying iterator over unmodified flags.
+    inner: FileReadableIterator<R, KPageFlags<K>>,
 
     ignored_flags: u64,
 }
@@ -27,10 +28,7 @@ pub struct KPageFlagsIterator<R: Read, K: Flaggy> {
 impl<R: Read, K: Flaggy> KPageFlagsIterator<R, K> {
     pub fn new(reader: KPageFlagsReader<R, K>, ignored_flags: &[K]) -> Self {
         KPageFlagsIterator {
-            reader,
-            buf: [KPageFlags::empty(); 1 << (21 - 3)],
-            nflags: 0,
-            idx: 0,
+            inner: FileReadableIterator::new(reader),
             ignored_flags: {
                 let mut mask: u64 = 0;
 
@@ -44,33 +42,21 @@ impl<R: Read, K: Flaggy> KPageFlagsIterator<R, K> {
     }
 }
 
+impl<R: Read, K: Flaggy> KPageFlagsIterator<R, K> {
+    /// Returns an iterator that panics on errors rather than producing them, e.g., for quick
+    /// scripts.
+    pub fn unwrapped(self) -> Unwrapped<Self> {
+        Unwrapped(self)
+    }
+}
+
 impl<R: Read, K: Flaggy> Iterator for KPageFlagsIterator<R, K> {
-    type Item = KPageFlags<K>;
+    type Item = Result<KPageFlags<K>, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        // Need to read some more?
-        if self.nflags == 0 {
-            self.nflags = match self.reader.read(&mut self.buf) {
-                Err(err) => {
-                    panic!("{:?}", err);
-                }
-
-                // EOF
-                Ok(0) => return None,
-
-                Ok(nflags) => nflags,
-            };
-            self.idx = 0;
-        }
-
-        // Return the first valid flags.
-        let mut item = self.buf[self.idx];
-
-        item.clear(self.ignored_flags.into());
-
-        self.nflags -= 1;
-        self.idx += 1;
-
-        Some(item)
+        Some(self.inner.next()?.map(|mut item| {
+            item.clear(self.ignored_flags.into());
+            item
+        }))
     }
 }
@@ -0,0 +1,218 @@
+//! Coalescing streams of flags into regions of consecutive pages.
+
+use super::{flags::Flaggy, KPageFlags};
+
+/// Decides whether consecutive pages can be combined into one region.
+pub trait CombinePolicy<K: Flaggy> {
+    /// Returns `true` if a page with flags `second` can be added to a region whose last page has
+    /// flags `first`.
+    fn can_combine(&self, first: KPageFlags<K>, second: KPageFlags<K>) -> bool;
+}
+
+/// The default policy: `KPageFlags::can_combine`, i.e., merge identical neighbours and compound
+/// head/tail sequences.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DefaultCombine;
+
+impl<K: Flaggy> CombinePolicy<K> for DefaultCombine {
+    fn can_combine(&self, first: KPageFlags<K>, second: KPageFlags<K>) -> bool {
+        KPageFlags::can_combine(first, second)
+    }
+}
+
+impl<K: Flaggy, F> CombinePolicy<K> for F
+where
+    F: Fn(KPageFlags<K>, KPageFlags<K>) -> bool,
+{
+    fn can_combine(&self, first: KPageFlags<K>, second: KPageFlags<K>) -> bool {
+        self(first, second)
+    }
+}
+
+/// A run of consecutive pages.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Region<K: Flaggy> {
+    pub start_pfn: u64,
+    pub npages: u64,
+    /// The flags of the first page in the region.
+    pub flags: KPageFlags<K>,
+}
+
+/// Turns an iterator over the flags of consecutive pages into an iterator over regions, according
+/// to some `CombinePolicy`.
+pub struct KPageFlagsRegions<I, K, P = DefaultCombine>
+where
+    I: Iterator<Item = KPageFlags<K>>,
+    K: Flaggy,
+    P: CombinePolicy<K>,
+{
+    iter: I,
+    policy: P,
+
+    /// The region being built, if any.
+    current: Option<Region<K>>,
+    /// The flags of the last page in `current`.
+    last: KPageFlags<K>,
+}
+
+impl<I, K> KPageFlagsRegions<I, K>
+where
+    I: Iterator<Item = KPageFlags<K>>,
+    K: Flaggy,
+{
+    /// Coalesces regions with the `DefaultCombine` policy. `iter` should start at PFN
+    /// `start_pfn`.
+    pub fn new(iter: I, start_pfn: u64) -> Self {
+        Self::with_policy(iter, start_pfn, DefaultCombine)
+    }
+}
+
+impl<I, K, P> KPageFlagsRegions<I, K, P>
+where
+    I: Iterator<Item = KPageFlags<K>>,
+    K: Flaggy,
+    P: CombinePolicy<K>,
+{
+    /// Coalesces regions with the given policy. `iter` should start at PFN `start_pfn`.
+    pub fn with_policy(iter: I, start_pfn: u64, policy: P) -> Self {
+        KPageFlagsRegions {
+            iter,
+            policy,
+            current: Some(Region {
+                start_pfn,
+                npages: 0,
+                flags: KPageFlags::empty(),
+            }),
+            last: KPageFlags::empty(),
+        }
+    }
+}
+
+impl<I, K, P> Iterator for KPageFlagsRegions<I, K, P>
+where
+    I: Iterator<Item = KPageFlags<K>>,
+    K: Flaggy,
+    P: CombinePolicy<K>,
+{
+    type Item = Region<K>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.current.as_mut()?;
+
+        for flags in self.iter.by_ref() {
+            if current.npages == 0 {
+                current.flags = flags;
+            } else if !self.policy.can_combine(self.last, flags) {
+                let done = *current;
+                *current = Region {
+                    start_pfn: done.start_pfn + done.npages,
+                    npages: 1,
+                    flags,
+                };
+                self.last = flags;
+                return Some(done);
+            }
+
+            current.npages += 1;
+            self.last = flags;
+        }
+
+        // Reached the end of the input, so produce whatever is left.
+        self.current.take().filter(|region| region.npages > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kpageflags::KPF6_0_0;
+
+    type Flags = KPF6_0_0::Flags;
+
+    fn regions<P: CombinePolicy<Flags>>(flags: &[Flags], policy: P) -> Vec<(u64, u64, Flags)> {
+        let pages = flags.iter().map(|flags| KPageFlags::from(*flags));
+        KPageFlagsRegions::with_policy(pages, 100, policy)
+            .map(|region| (region.start_pfn, region.npages, region.flags.0))
+            .collect()
+    }
+
+    #[test]
+    fn compound_pages_are_one_region() {
+        let head = KPF6_0_0::CompoundHead | KPF6_0_0::Thp;
+        let tail = KPF6_0_0::CompoundTail | KPF6_0_0::Thp;
+
+        assert_eq!(
+            regions(
+                &[KPF6_0_0::Lru, head, tail, tail, tail, KPF6_0_0::Lru],
+                DefaultCombine
+            ),
+            [
+                (100, 1, KPF6_0_0::Lru),
+                (101, 4, head),
+                (105, 1, KPF6_0_0::Lru)
+            ]
+        );
+    }
+
+    #[test]
+    fn adjacent_compound_pages_are_separate() {
+        let head = KPF6_0_0::CompoundHead | KPF6_0_0::Slab;
+        let tail = KPF6_0_0::CompoundTail | KPF6_0_0::Slab;
+
+        assert_eq!(
+            regions(&[head, tail, head, tail, tail], DefaultCombine),
+            [(100, 2, head), (102, 3, head)]
+        );
+    }
+
+    #[test]
+    fn identical_pages_are_one_region() {
+        assert_eq!(
+            regions(
+                &[
+                    KPF6_0_0::Buddy,
+                    KPF6_0_0::Buddy,
+                    KPF6_0_0::Slab,
+                    KPF6_0_0::Buddy
+                ],
+                DefaultCombine
+            ),
+            [
+                (100, 2, KPF6_0_0::Buddy),
+                (102, 1, KPF6_0_0::Slab),
+                (103, 1, KPF6_0_0::Buddy),
+            ]
+        );
+    }
+
+    #[test]
+    fn closures_are_policies() {
+        // Combine all free pages, regardless of other flags.
+        let free = |first: KPageFlags<Flags>, second: KPageFlags<Flags>| {
+            first.all(KPF6_0_0::Buddy) == second.all(KPF6_0_0::Buddy)
+        };
+
+        assert_eq!(
+            regions(
+                &[
+                    KPF6_0_0::Buddy,
+                    KPF6_0_0::Buddy | KPF6_0_0::Idle,
+                    KPF6_0_0::Lru,
+                    KPF6_0_0::Slab,
+                ],
+                free
+            ),
+            [(100, 2, KPF6_0_0::Buddy), (102, 2, KPF6_0_0::Lru)]
+        );
+        assert_eq!(regions(&[KPF6_0_0::Lru; 3], |_, _| false).len(), 3);
+    }
+
+    #[test]
+    fn empty_input_has_no_regions() {
+        assert!(regions(&[], DefaultCombine).is_empty());
+
+        let mut regions = KPageFlagsRegions::new(std::iter::empty::<KPageFlags<Flags>>(), 0);
+        assert_eq!(regions.next(), None);
+        assert_eq!(regions.next(), None);
+    }
+}
@@ -0,0 +1,204 @@
+// The single table of kpageflags across kernel versions. `build.rs` generates a `KPF*` module for
+// each entry of `LAYOUTS` from it, so this file must not depend on anything else in the crate.
+// Not everything here is used by both.
+#![allow(dead_code)]
+
+/// A kernel version as `(major, minor, patch)`.
+type Version = (u32, u32, u32);
+
+/// A flag in `/proc/kpageflags`, and the kernel versions that report it at this bit.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct FlagSpec {
+    /// The name of the flag, as in `KPF6_0_0::Buddy`.
+    pub name: &'static str,
+    pub bit: u32,
+    /// The first layout with this flag.
+    pub(crate) since: Version,
+    /// The first layout without this flag again, if any.
+    pub(crate) until: Option<Version>,
+}
+
+const fn flag(name: &'static str, bit: u32, since: Version, until: Option<Version>) -> FlagSpec {
+    FlagSpec {
+        name,
+        bit,
+        since,
+        until,
+    }
+}
+
+impl FlagSpec {
+    /// Returns `true` if the layout for `version` (one of `LAYOUTS`) has this flag.
+    pub(crate) fn in_layout(&self, version: Version) -> bool {
+        self.since <= version && self.until.is_none_or(|until| version < until)
+    }
+}
+
+/// The kernel versions we have layouts for, oldest first. This must match `KernelLayout::ALL`.
+pub const LAYOUTS: &[Version] = &[
+    (3, 10, 0),
+    (4, 15, 0),
+    (5, 0, 8),
+    (5, 4, 0),
+    (5, 13, 0),
+    (5, 15, 0),
+    (5, 17, 0),
+    (5, 19, 0),
+    (6, 0, 0),
+    (6, 1, 0),
+    (6, 6, 0),
+    (6, 12, 0),
+];
+
+/// Every flag, in order of bit. Bits 0-26 are from `include/uapi/linux/kernel-page-flags.h`, bits
+/// 32-42 from `include/linux/kernel-page-flags.h`, and the rest are overloaded flags defined by
+/// `tools/mm/page-types.c`. The tests in `header.rs` check the LTS layouts against copies of those
+/// files in `testdata/kernel`.
+#[rustfmt::skip]
+pub const FLAGS: &[FlagSpec] = &[
+    flag("Locked",        0,  (3, 10, 0), None),
+    // `PG_error` and `KPF_ERROR` are gone in 6.12.
+    flag("Error",         1,  (3, 10, 0), Some((6, 12, 0))),
+    flag("Referenced",    2,  (3, 10, 0), None),
+    flag("Uptodate",      3,  (3, 10, 0), None),
+    flag("Dirty",         4,  (3, 10, 0), None),
+    flag("Lru",           5,  (3, 10, 0), None),
+    flag("Active",        6,  (3, 10, 0), None),
+    flag("Slab",          7,  (3, 10, 0), None),
+    flag("Writeback",     8,  (3, 10, 0), None),
+    flag("Reclaim",       9,  (3, 10, 0), None),
+    flag("Buddy",         10, (3, 10, 0), None),
+    flag("Mmap",          11, (3, 10, 0), None),
+    flag("Anon",          12, (3, 10, 0), None),
+    flag("Swapcache",     13, (3, 10, 0), None),
+    flag("Swapbacked",    14, (3, 10, 0), None),
+    flag("CompoundHead",  15, (3, 10, 0), None),
+    flag("CompoundTail",  16, (3, 10, 0), None),
+    flag("Huge",          17, (3, 10, 0), None),
+    flag("Unevictable",   18, (3, 10, 0), None),
+    flag("Hwpoison",      19, (3, 10, 0), None),
+    flag("Nopage",        20, (3, 10, 0), None),
+    flag("Ksm",           21, (3, 10, 0), None),
+    flag("Thp",           22, (3, 10, 0), None),
+    flag("Balloon",       23, (3, 10, 0), Some((5, 0, 8))),
+    flag("Offline",       23, (5, 0, 8),  None),
+    flag("ZeroPage",      24, (3, 10, 0), None),
+    flag("Idle",          25, (3, 10, 0), None),
+    flag("Pgtable",       26, (5, 0, 8),  None),
+
+    flag("Reserved",      32, (3, 10, 0), None),
+    flag("Mlocked",       33, (3, 10, 0), None),
+    flag("Mappedtodisk",  34, (3, 10, 0), None),
+    flag("Private",       35, (3, 10, 0), None),
+    flag("Private2",      36, (3, 10, 0), None),
+    flag("OwnerPrivate",  37, (3, 10, 0), None),
+    flag("Arch",          38, (3, 10, 0), None),
+    flag("Uncached",      39, (3, 10, 0), None),
+    flag("Softdirty",     40, (4, 15, 0), None),
+    flag("Arch2",         41, (5, 13, 0), None),
+    flag("Arch3",         42, (6, 6, 0),  None),
+
+    flag("AnonExclusive", 47, (5, 19, 0), None),
+    flag("Readahead",     48, (3, 10, 0), None),
+    // SLOB was removed in 6.4.
+    flag("Slobfree",      49, (3, 10, 0), Some((6, 6, 0))),
+    flag("Slubfrozen",    50, (3, 10, 0), None),
+    flag("Slubdebug",     51, (3, 10, 0), None),
+
+    flag("File",          61, (4, 15, 0), None),
+    flag("Swap",          62, (4, 15, 0), None),
+    flag("MmapExclusive", 63, (4, 15, 0), None),
+];
+
+/// Flags whose bit was later reused by another flag, and the bit they have in the canonical layout
+/// instead. This must be a bit that no other flag uses.
+pub const CANONICAL_BITS: &[(&str, u32)] = &[("Balloon", 60)];
+
+/// Returns the flags of the canonical layout, which has every flag of every kernel: each flag by
+/// name once, at its bit in `FLAGS` unless it is in `CANONICAL_BITS`. Sorted by bit.
+pub fn canonical_flags() -> Vec<(&'static str, u32)> {
+    let mut flags: Vec<(&'static str, u32)> = Vec::new();
+    for flag in FLAGS {
+        if flags.iter().any(|(name, _)| *name == flag.name) {
+            continue;
+        }
+        let bit = CANONICAL_BITS
+            .iter()
+            .find(|(name, _)| *name == flag.name)
+            .map_or(flag.bit, |(_, bit)| *bit);
+        flags.push((flag.name, bit));
+    }
+    flags.sort_by_key(|(_, bit)| *bit);
+    flags
+}
+
+/// The constants of `Flaggy`, and the flag each one is. Constants of type `Option<Self>` are
+/// `None` in layouts without the flag.
+pub const FLAGGY_CONSTS: &[(&str, &str)] = &[
+    ("NOPAGE", "Nopage"),
+    ("COMPOUND_HEAD", "CompoundHead"),
+    ("COMPOUND_TAIL", "CompoundTail"),
+    ("PGTABLE", "Pgtable"),
+    ("OFFLINE", "Offline"),
+    ("BUDDY", "Buddy"),
+    ("SLAB", "Slab"),
+    ("RESERVED", "Reserved"),
+    ("MMAP", "Mmap"),
+    ("LRU", "Lru"),
+    ("ANON", "Anon"),
+    ("THP", "Thp"),
+    ("IDLE", "Idle"),
+    ("PRIVATE", "Private"),
+    ("PRIVATE2", "Private2"),
+    ("OWNERPRIVATE1", "OwnerPrivate"),
+];
+
+/// The `Flaggy` constants of type `Option<Self>`.
+pub const OPTIONAL_CONSTS: &[&str] = &["PGTABLE", "OFFLINE"];
+
+/// Renders a `kpf!` invocation for a layout named `module` with the given flags and bits, with
+/// the `Flaggy` constants filled in. Fails if two flags share a bit, or if a flag needed by a
+/// non-optional `Flaggy` constant is missing.
+pub fn kpf_block(module: &str, flags: &[(&str, u32)]) -> Result<String, String> {
+    use std::fmt::Write;
+
+    let mut flags = flags.to_vec();
+    flags.sort_by_key(|(_, bit)| *bit);
+    for pair in flags.windows(2) {
+        if pair[0].1 == pair[1].1 {
+            return Err(format!(
+                "{} and {} are both at bit {} in {module}",
+                pair[0].0, pair[1].0, pair[0].1
+            ));
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(out, "kpf! {{").unwrap();
+    writeln!(out, "    {module} {{").unwrap();
+    for (name, bit) in flags.iter() {
+        writeln!(out, "        {name} = {bit},").unwrap();
+    }
+    writeln!(out, "    }}").unwrap();
+    writeln!(out).unwrap();
+
+    for (constant, name) in FLAGGY_CONSTS {
+        let present = flags.iter().any(|(flag, _)| flag == name);
+        if OPTIONAL_CONSTS.contains(constant) {
+            if present {
+                writeln!(out, "    {constant}: Option<Self> = Some({name});").unwrap();
+            } else {
+                writeln!(out, "    {constant}: Option<Self> = None;").unwrap();
+            }
+        } else if present {
+            writeln!(out, "    {constant}: Self = {name};").unwrap();
+        } else {
+            return Err(format!(
+                "{module} has no {name} flag, needed for {constant}"
+            ));
+        }
+    }
+    writeln!(out, "}}").unwrap();
+
+    Ok(out)
+}
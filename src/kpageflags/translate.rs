@@ -0,0 +1,168 @@
+//! Translating flags between layouts by name, e.g., to compare captures from different kernels in
+//! one vocabulary (such as `Canonical`).
+
+use super::{flags::Flaggy, KPageFlags};
+
+/// Maps the flags of layout `F` to the flags of layout `T` with the same names.
+#[derive(Clone, Debug)]
+pub struct Translation<F: Flaggy, T: Flaggy> {
+    /// Each flag of `F` that `T` also has, and the flag in `T`.
+    pairs: Vec<(F, T)>,
+    /// The flags of `F` that `T` doesn't have.
+    missing: F,
+}
+
+impl<F: Flaggy, T: Flaggy> Translation<F, T> {
+    pub fn new() -> Self {
+        let mut pairs = Vec::new();
+        let mut missing = F::empty();
+
+        for &flag in F::values() {
+            match flag.name().and_then(|name| name.parse::<T>().ok()) {
+                Some(target) => pairs.push((flag, target)),
+                None => missing |= flag,
+            }
+        }
+
+        Translation { pairs, missing }
+    }
+
+    /// Returns the flags of `F` that `T` doesn't have, which are dropped by `translate`.
+    pub fn missing(&self) -> F {
+        self.missing
+    }
+
+    /// Returns the names of the flags of `F` that `T` doesn't have.
+    pub fn missing_names(&self) -> Vec<&'static str> {
+        F::values()
+            .iter()
+            .filter(|flag| **flag & self.missing != F::empty())
+            .filter_map(|flag| flag.name())
+            .collect()
+    }
+
+    /// Translates `flags` to `T`. Also returns the flags that were dropped because `T` doesn't
+    /// have them, including any bits that aren't flags of `F` at all.
+    pub fn translate(&self, flags: KPageFlags<F>) -> (KPageFlags<T>, F) {
+        let mut translated = T::empty();
+        let mut dropped = flags.0;
+
+        for (from, to) in self.pairs.iter() {
+            if flags.all(*from) {
+                translated |= *to;
+                dropped &= !*from;
+            }
+        }
+
+        (KPageFlags(translated), dropped)
+    }
+
+    /// Translates a single flag of `F` to `T`, if `T` has it.
+    pub fn translate_flag(&self, flag: F) -> Option<T> {
+        self.pairs
+            .iter()
+            .find(|(from, _)| *from == flag)
+            .map(|(_, to)| *to)
+    }
+}
+
+impl<F: Flaggy, T: Flaggy> Default for Translation<F, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Translates `flags` from layout `F` to layout `T` by name. Also returns the flags that were
+/// dropped because `T` doesn't have them. To translate many flags, use a `Translation`.
+pub fn translate<F: Flaggy, T: Flaggy>(flags: KPageFlags<F>) -> (KPageFlags<T>, F) {
+    Translation::new().translate(flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        kernel::{KernelLayout, LayoutVisitor},
+        kpageflags::{Canonical, FlagHistogram, KPF3_10_0, KPF6_0_0},
+        pagemap::PageMappy,
+    };
+
+    /// Checks that every layout translates to `Canonical` and back without losing any flag.
+    struct RoundTrip;
+
+    impl LayoutVisitor for RoundTrip {
+        type Output = ();
+
+        fn visit<K: Flaggy, P: PageMappy>(self) {
+            let to = Translation::<K, Canonical::Flags>::new();
+            let from = Translation::<Canonical::Flags, K>::new();
+            assert_eq!(to.missing(), K::empty(), "{:?}", to.missing_names());
+
+            let all = KPageFlags(K::valid_mask());
+            let (canonical, dropped) = to.translate(all);
+            assert_eq!(dropped, K::empty());
+            assert_eq!(canonical.as_u64().count_ones(), all.as_u64().count_ones());
+            let (back, dropped) = from.translate(canonical);
+            assert_eq!(back, all);
+            assert_eq!(dropped, Canonical::Flags::empty());
+
+            for &flag in K::values() {
+                let canonical = to.translate_flag(flag).unwrap();
+                assert_eq!(from.translate_flag(canonical), Some(flag));
+            }
+        }
+    }
+
+    #[test]
+    fn every_layout_round_trips_through_canonical() {
+        for layout in KernelLayout::ALL {
+            layout.dispatch(RoundTrip);
+        }
+    }
+
+    #[test]
+    fn reused_bits_stay_apart() {
+        let balloon = KPageFlags::from(KPF3_10_0::Balloon);
+        let offline = KPageFlags::from(KPF6_0_0::Offline);
+        assert_eq!(balloon.as_u64(), offline.as_u64());
+
+        let (balloon, _) = translate::<_, Canonical::Flags>(balloon);
+        let (offline, _) = translate::<_, Canonical::Flags>(offline);
+        assert_eq!(balloon, KPageFlags::from(Canonical::Balloon));
+        assert_eq!(offline, KPageFlags::from(Canonical::Offline));
+        assert_ne!(balloon, offline);
+
+        let mut old = FlagHistogram::<KPF3_10_0::Flags>::new();
+        old.add_n(KPageFlags::from(KPF3_10_0::Balloon), 3);
+        let mut new = FlagHistogram::<KPF6_0_0::Flags>::new();
+        new.add_n(KPageFlags::from(KPF6_0_0::Offline), 5);
+        let (mut merged, _) = old.translate(&Translation::new());
+        merged.merge(&new.translate(&Translation::new()).0);
+        assert_eq!(merged.get(balloon), 3);
+        assert_eq!(merged.get(offline), 5);
+    }
+
+    #[test]
+    fn missing_flags_are_dropped() {
+        let translation = Translation::<KPF6_0_0::Flags, KPF3_10_0::Flags>::new();
+        assert_eq!(
+            translation.missing_names(),
+            [
+                "Offline",
+                "Pgtable",
+                "Softdirty",
+                "Arch2",
+                "AnonExclusive",
+                "File",
+                "Swap",
+                "MmapExclusive",
+            ]
+        );
+
+        let flags = KPageFlags::from(KPF6_0_0::Offline | KPF6_0_0::Buddy);
+        let (translated, dropped) = translation.translate(flags);
+        assert_eq!(translated, KPageFlags::from(KPF3_10_0::Buddy));
+        assert_eq!(dropped, KPF6_0_0::Offline);
+        assert_eq!(translation.translate_flag(KPF6_0_0::Offline), None);
+    }
+}
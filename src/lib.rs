@@ -1,12 +1,33 @@
-//! Tools for reading `/proc/kpageflags` and `/proc/[self]/pagemap`.
+//! Tools for reading `/proc/kpageflags`, `/proc/kpagecount`, and `/proc/[self]/pagemap`.
 
 use std::{
+    fs::File,
     io::{self, BufRead, BufReader, Read},
     marker::PhantomData,
+    ops::Range,
+    os::unix::fs::FileExt,
+    path::Path,
 };
 
+pub mod compress;
+pub mod error;
+pub mod kernel;
+pub mod kpagecgroup;
+pub mod kpagecount;
 pub mod kpageflags;
+pub mod maps;
+pub mod memory_block;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub mod page_idle;
 pub mod pagemap;
+pub mod resolve;
+pub mod snapshot;
+pub mod topology;
+
+pub use error::Error;
+
+use compress::looks_compressed;
 
 /// Indicates that the implementing type can be cast directly from the contents of a file.
 ///
@@ -15,9 +36,40 @@ pub mod pagemap;
 /// UB.
 pub unsafe trait FileReadable {}
 
+/// Returns the size of a base page on this system in bytes.
+pub fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// Allocates a buffer of `n` zeroed `FileReadable` values.
+fn zeroed_buf<T: FileReadable>(n: usize) -> Vec<T> {
+    // Safe because `FileReadable` types can be cast from any bytes, including zeros.
+    (0..n).map(|_| unsafe { std::mem::zeroed() }).collect()
+}
+
+/// Casts a buffer of `FileReadable` values as an array of bytes to write out.
+fn as_bytes<T: FileReadable>(buf: &[T]) -> &[u8] {
+    unsafe {
+        let ptr: *const u8 = buf.as_ptr() as *const u8;
+        let len = std::mem::size_of_val(buf);
+        std::slice::from_raw_parts(ptr, len)
+    }
+}
+
+/// Casts a buffer of `FileReadable` values as an array of bytes to read into.
+fn as_bytes_mut<T: FileReadable>(buf: &mut [T]) -> &mut [u8] {
+    unsafe {
+        let ptr: *mut u8 = buf.as_mut_ptr() as *mut u8;
+        let len = std::mem::size_of_val(buf);
+        std::slice::from_raw_parts_mut(ptr, len)
+    }
+}
+
 /// A reader for `FileReadable` types.
 pub struct FileReadableReader<R: Read, T: FileReadable> {
     reader: BufReader<R>,
+    /// Whether the start of the input looks like compressed data, once we have seen it.
+    looks_compressed: Option<bool>,
     _phantom: PhantomData<T>,
 }
 
@@ -25,21 +77,33 @@ impl<R: Read, T: FileReadable> FileReadableReader<R, T> {
     pub fn new(reader: BufReader<R>) -> Self {
         FileReadableReader {
             reader,
+            looks_compressed: None,
             _phantom: PhantomData,
         }
     }
+}
 
+impl<T: FileReadable> FileReadableReader<Box<dyn Read>, T> {
+    /// Opens the file at `path`, decompressing it on the fly if it is compressed in a format
+    /// enabled by a cargo feature. See `compress::decompress`.
+    pub fn open_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufReader::new(compress::decompress(
+            File::open(path)?,
+        )?)))
+    }
+}
+
+impl<R: Read, T: FileReadable> FileReadableReader<R, T> {
     /// Similar to `Read::read`, but reads the bytes as `PageMapPage`, and returns the number of
     /// flags in the buffer, rather than the number of bytes.
+    ///
+    /// If the input ends in the middle of a record, the returned error wraps an `Error::Truncated`
+    /// or `Error::Compressed`, which can be recovered with `Error::from`.
     pub fn read(&mut self, orig_buf: &mut [T]) -> io::Result<usize> {
         let size = std::mem::size_of::<T>();
 
         // Cast as an array of bytes to do the read.
-        let mut buf: &mut [u8] = unsafe {
-            let ptr: *mut u8 = orig_buf.as_mut_ptr() as *mut u8;
-            let len = orig_buf.len() * size;
-            std::slice::from_raw_parts_mut(ptr, len)
-        };
+        let mut buf: &mut [u8] = as_bytes_mut(orig_buf);
 
         // Manually read from the buffer so that we can stop at a proper KPF boundary.
         let mut total_bytes_read = 0;
@@ -49,6 +113,10 @@ impl<R: Read, T: FileReadable> FileReadableReader<R, T> {
             self.reader.buffer()
         };
 
+        if self.looks_compressed.is_none() && !filled_buf.is_empty() {
+            self.looks_compressed = Some(looks_compressed(filled_buf));
+        }
+
         // Until we read enough...
         loop {
             match filled_buf.len() {
@@ -88,14 +156,410 @@ impl<R: Read, T: FileReadable> FileReadableReader<R, T> {
         }
 
         // Error: maybe the file was gzipped? Check for the sake of error reporting.
-        if total_bytes_read % size != 0 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Total number of bytes read is not a multiple of struct size. \
-                 Perhaps the data is compressed?",
-            ));
+        let trailing_bytes = total_bytes_read % size;
+        if trailing_bytes != 0 {
+            return Err(if self.looks_compressed == Some(true) {
+                Error::Compressed
+            } else {
+                Error::Truncated { trailing_bytes }
+            }
+            .into());
+        }
+
+        Ok(total_bytes_read / size)
+    }
+}
+
+/// Turns a `FileReadableReader` into a proper (efficient) iterator over records.
+///
+/// A read error ends the iteration: it is returned once, and `next` returns `None` after it,
+/// since the position in the input is unknown after a failed read.
+pub struct FileReadableIterator<R: Read, T: FileReadable> {
+    /// The reader we are reading from.
+    reader: FileReadableReader<R, T>,
+
+    /// Temporary buffer for data read but not consumed yet.
+    buf: Vec<T>,
+    /// The number of valid records in the buffer.
+    nrecords: usize,
+    /// The index of the first valid, unconsumed record in the buffer, if `nrecords > 0`.
+    idx: usize,
+    /// Whether a read has failed, which ends the iteration.
+    failed: bool,
+}
+
+impl<R: Read, T: FileReadable> FileReadableIterator<R, T> {
+    pub fn new(reader: FileReadableReader<R, T>) -> Self {
+        FileReadableIterator {
+            reader,
+            buf: zeroed_buf((1 << 21) / std::mem::size_of::<T>()),
+            nrecords: 0,
+            idx: 0,
+            failed: false,
+        }
+    }
+}
+
+impl<R: Read, T: FileReadable + Copy> FileReadableIterator<R, T> {
+    /// Returns an iterator that panics on errors rather than producing them, e.g., for quick
+    /// scripts.
+    pub fn unwrapped(self) -> Unwrapped<Self> {
+        Unwrapped(self)
+    }
+}
+
+impl<R: Read, T: FileReadable + Copy> Iterator for FileReadableIterator<R, T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Need to read some more?
+        if self.nrecords == 0 {
+            if self.failed {
+                return None;
+            }
+
+            self.nrecords = match self.reader.read(&mut self.buf) {
+                Err(err) => {
+                    self.failed = true;
+                    return Some(Err(err.into()));
+                }
+
+                // EOF
+                Ok(0) => return None,
+
+                Ok(nrecords) => nrecords,
+            };
+            self.idx = 0;
+        }
+
+        // Return the first valid record.
+        let item = self.buf[self.idx];
+
+        self.nrecords -= 1;
+        self.idx += 1;
+
+        Some(Ok(item))
+    }
+}
+
+/// Wraps an iterator over `Result`s, panicking on errors rather than producing them. See
+/// `FileReadableIterator::unwrapped`.
+pub struct Unwrapped<I>(I);
+
+impl<I, T> Iterator for Unwrapped<I>
+where
+    I: Iterator<Item = Result<T, Error>>,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0
+            .next()
+            .map(|item| item.unwrap_or_else(|err| panic!("{}", err)))
+    }
+}
+
+/// A random-access reader for files of `FileReadable` types, indexed by record rather than by
+/// byte. Unlike `FileReadableReader`, this uses positioned reads, so it can start anywhere in the
+/// file.
+pub struct FileReadableFile<T: FileReadable> {
+    file: File,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: FileReadable> FileReadableFile<T> {
+    pub fn new(file: File) -> Self {
+        FileReadableFile {
+            file,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(File::open(path)?))
+    }
+
+    /// Similar to `FileExt::read_at`, but reads records starting at record `idx`, and returns the
+    /// number of records read. Fewer records than requested are only returned at EOF.
+    pub fn read_at(&self, idx: u64, buf: &mut [T]) -> io::Result<usize> {
+        let size = std::mem::size_of::<T>();
+        let offset = idx * size as u64;
+        let buf = as_bytes_mut(buf);
+
+        let mut total_bytes_read = 0;
+        while total_bytes_read < buf.len() {
+            match self.file.read_at(
+                &mut buf[total_bytes_read..],
+                offset + total_bytes_read as u64,
+            ) {
+                // Reached EOF
+                Ok(0) => break,
+                Ok(n) => total_bytes_read += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        let trailing_bytes = total_bytes_read % size;
+        if trailing_bytes != 0 {
+            return Err(Error::Truncated { trailing_bytes }.into());
         }
 
         Ok(total_bytes_read / size)
     }
+
+    /// Returns the number of records in the file. Files in `/proc` report a size of 0, so their
+    /// length is found by probing with reads instead.
+    pub fn record_count(&self) -> io::Result<u64> {
+        let size = std::mem::size_of::<T>() as u64;
+        let len = self.file.metadata()?.len();
+        if len > 0 {
+            return Ok(len / size);
+        }
+
+        let mut buf = zeroed_buf(1);
+        let mut exists = |idx: u64| -> io::Result<bool> { Ok(self.read_at(idx, &mut buf)? > 0) };
+
+        // Find an upper bound, then binary search for the first index past the end.
+        let mut hi = 1;
+        while exists(hi - 1)? {
+            hi *= 2;
+        }
+        let mut lo = hi / 2;
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if exists(mid)? {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+
+        Ok(lo)
+    }
+
+    /// Reads the record at index `idx`.
+    pub fn get(&self, idx: u64) -> io::Result<T> {
+        let mut buf = zeroed_buf(1);
+        match self.read_at(idx, &mut buf)? {
+            0 => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("record {idx} is past the end of the file"),
+            )),
+            _ => Ok(buf.pop().unwrap()),
+        }
+    }
+
+    /// Reads the records in the given range of indices. The result is truncated if the range
+    /// extends past the end of the file.
+    pub fn read_range(&self, range: Range<u64>) -> io::Result<Vec<T>> {
+        let mut buf = zeroed_buf(range.end.saturating_sub(range.start) as usize);
+        let n = self.read_at(range.start, &mut buf)?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    /// Returns an iterator over the records of the file, starting at index `idx`.
+    pub fn iter_from(&self, idx: u64) -> FileReadableFileIterator<'_, T> {
+        self.iter_range(idx..u64::MAX)
+    }
+
+    /// Returns an iterator over the records in the given range of indices, reading a bounded chunk
+    /// at a time. The iterator ends early if the range extends past the end of the file.
+    pub fn iter_range(&self, range: Range<u64>) -> FileReadableFileIterator<'_, T> {
+        let max_records = ((1 << 21) / std::mem::size_of::<T>()) as u64;
+        let len = range.end.saturating_sub(range.start).min(max_records);
+
+        FileReadableFileIterator {
+            file: self,
+            next_idx: range.start,
+            end_idx: range.end,
+            buf: zeroed_buf(len as usize),
+            nrecords: 0,
+            idx: 0,
+            failed: false,
+        }
+    }
+}
+
+/// An iterator over the records of a `FileReadableFile`, reading a chunk at a time. Like
+/// `FileReadableIterator`, it ends after the first read error.
+pub struct FileReadableFileIterator<'f, T: FileReadable> {
+    file: &'f FileReadableFile<T>,
+    /// The index in the file of the next record to read into the buffer.
+    next_idx: u64,
+    /// The index in the file to stop at.
+    end_idx: u64,
+
+    /// Temporary buffer for data read but not consumed yet.
+    buf: Vec<T>,
+    /// The number of valid records in the buffer.
+    nrecords: usize,
+    /// The index of the first valid, unconsumed record in the buffer, if `nrecords > 0`.
+    idx: usize,
+    /// Whether a read has failed, which ends the iteration.
+    failed: bool,
+}
+
+impl<'f, T: FileReadable + Copy> FileReadableFileIterator<'f, T> {
+    /// Returns an iterator that panics on errors rather than producing them.
+    pub fn unwrapped(self) -> Unwrapped<Self> {
+        Unwrapped(self)
+    }
+}
+
+impl<T: FileReadable + Copy> Iterator for FileReadableFileIterator<'_, T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Need to read some more?
+        if self.nrecords == 0 {
+            if self.failed {
+                return None;
+            }
+
+            let len = self
+                .end_idx
+                .saturating_sub(self.next_idx)
+                .min(self.buf.len() as u64) as usize;
+            self.nrecords = match self.file.read_at(self.next_idx, &mut self.buf[..len]) {
+                Err(err) => {
+                    self.failed = true;
+                    return Some(Err(err.into()));
+                }
+
+                // EOF
+                Ok(0) => return None,
+
+                Ok(nrecords) => nrecords,
+            };
+            self.next_idx += self.nrecords as u64;
+            self.idx = 0;
+        }
+
+        let item = self.buf[self.idx];
+
+        self.nrecords -= 1;
+        self.idx += 1;
+
+        Some(Ok(item))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kpagecount::{KPageCount, KPageCountIterator};
+
+    /// Three whole records, and half of a fourth.
+    fn truncated() -> Vec<u8> {
+        let mut bytes: Vec<u8> = (1..=3u64).flat_map(u64::to_ne_bytes).collect();
+        bytes.extend_from_slice(&[0; 4]);
+        bytes
+    }
+
+    fn records(bytes: &[u8]) -> KPageCountIterator<&[u8]> {
+        FileReadableIterator::new(FileReadableReader::new(BufReader::new(bytes)))
+    }
+
+    #[test]
+    fn iterator_yields_errors() {
+        let bytes = truncated();
+        let items: Vec<Result<KPageCount, Error>> = records(&bytes).collect();
+
+        assert_eq!(items.len(), 4);
+        let counts: Vec<_> = items[..3]
+            .iter()
+            .map(|item| item.as_ref().unwrap().as_u64())
+            .collect();
+        assert_eq!(counts, [1, 2, 3]);
+        assert!(matches!(
+            items[3],
+            Err(Error::Truncated { trailing_bytes: 4 })
+        ));
+    }
+
+    /// A reader that returns each chunk in turn from a separate call to `read`.
+    struct Chunks(Vec<io::Result<Vec<u8>>>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+
+            let chunk = self.0.remove(0)?;
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn iterator_stops_after_an_error() {
+        let chunk = |counts: &[u64]| {
+            Ok(counts
+                .iter()
+                .flat_map(|count| count.to_ne_bytes())
+                .collect())
+        };
+        let reader = Chunks(vec![
+            chunk(&[1, 2]),
+            Err(io::Error::other("flaky")),
+            chunk(&[3, 4]),
+        ]);
+        let mut counts = KPageCountIterator::new(FileReadableReader::new(BufReader::new(reader)));
+
+        assert_eq!(counts.next().unwrap().unwrap().as_u64(), 1);
+        assert_eq!(counts.next().unwrap().unwrap().as_u64(), 2);
+        assert!(matches!(counts.next(), Some(Err(Error::Io(_)))));
+        assert!(counts.next().is_none());
+        assert!(counts.next().is_none());
+
+        // A truncated record is the last thing read anyway.
+        let bytes = truncated();
+        assert_eq!(records(&bytes).count(), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "incomplete record")]
+    fn unwrapped_panics_on_errors() {
+        let bytes = truncated();
+        records(&bytes).unwrapped().for_each(drop);
+    }
+
+    #[test]
+    fn unwrapped_yields_records() {
+        let bytes: Vec<u8> = (1..=3u64).flat_map(u64::to_ne_bytes).collect();
+        let counts: Vec<_> = records(&bytes)
+            .unwrapped()
+            .map(KPageCount::as_u64)
+            .collect();
+        assert_eq!(counts, [1, 2, 3]);
+    }
+
+    #[test]
+    fn kpageflags_iterator_clears_ignored_flags() {
+        use crate::kpageflags::{KPageFlagsIterator, KPageFlagsReader, KPF6_0_0};
+
+        let bytes: Vec<u8> = [KPF6_0_0::Buddy | KPF6_0_0::Idle, KPF6_0_0::Lru]
+            .into_iter()
+            .flat_map(|flags| u64::from(flags).to_ne_bytes())
+            .collect();
+        let reader = KPageFlagsReader::<_, KPF6_0_0::Flags>::new(BufReader::new(&bytes[..]));
+
+        let flags: Vec<_> = KPageFlagsIterator::new(reader, &[KPF6_0_0::Idle])
+            .map(|flags| flags.unwrap().as_u64())
+            .collect();
+        assert_eq!(
+            flags,
+            [u64::from(KPF6_0_0::Buddy), u64::from(KPF6_0_0::Lru)]
+        );
+    }
+
+    #[test]
+    fn kernel_errors_convert() {
+        let err = Error::from(crate::kernel::KernelError::Unparseable("foo".into()));
+        assert!(matches!(err, Error::Kernel(_)));
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::Unsupported);
+    }
 }
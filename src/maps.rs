@@ -0,0 +1,376 @@
+//! Tools for reading `/proc/[pid]/maps` and `/proc/[pid]/smaps`, and joining them with pagemap.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    str::FromStr,
+};
+
+use crate::pagemap::{PageMapFile, PageMapPage, PageMappy};
+
+/// The permissions of a mapping, e.g., `rw-p`.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Perms {
+    pub read: bool,
+    pub write: bool,
+    pub exec: bool,
+    /// `true` for shared mappings, `false` for private (copy-on-write) ones.
+    pub shared: bool,
+}
+
+impl FromStr for Perms {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            [r, w, x, p] => Ok(Perms {
+                read: *r == b'r',
+                write: *w == b'w',
+                exec: *x == b'x',
+                shared: *p == b's',
+            }),
+            _ => Err(format!("invalid permissions: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Perms {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}{}{}",
+            if self.read { 'r' } else { '-' },
+            if self.write { 'w' } else { '-' },
+            if self.exec { 'x' } else { '-' },
+            if self.shared { 's' } else { 'p' },
+        )
+    }
+}
+
+/// A single virtual memory area, i.e., one line of `/proc/[pid]/maps`.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Vma {
+    pub start: usize,
+    pub end: usize,
+    pub perms: Perms,
+    pub offset: u64,
+    /// The (major, minor) device numbers of the backing file.
+    pub dev: (u32, u32),
+    pub inode: u64,
+    /// The backing file or pseudo-path (e.g., `[heap]`), if any.
+    pub path: Option<String>,
+}
+
+impl Vma {
+    /// The length of the mapping in bytes.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+impl FromStr for Vma {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid maps line: {}", s);
+
+        // The path may contain spaces, so peel off the other fields one at a time.
+        let mut rest = s.trim_start();
+        let mut fields = [""; 5];
+        for field in fields.iter_mut() {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            *field = &rest[..end];
+            rest = rest[end..].trim_start();
+        }
+        let [range, perms, offset, dev, inode] = fields;
+
+        let (start, end) = range.split_once('-').ok_or_else(err)?;
+        let (major, minor) = dev.split_once(':').ok_or_else(err)?;
+
+        Ok(Vma {
+            start: usize::from_str_radix(start, 16).map_err(|_| err())?,
+            end: usize::from_str_radix(end, 16).map_err(|_| err())?,
+            perms: perms.parse()?,
+            offset: u64::from_str_radix(offset, 16).map_err(|_| err())?,
+            dev: (
+                u32::from_str_radix(major, 16).map_err(|_| err())?,
+                u32::from_str_radix(minor, 16).map_err(|_| err())?,
+            ),
+            inode: inode.parse().map_err(|_| err())?,
+            path: if rest.is_empty() {
+                None
+            } else {
+                Some(rest.to_owned())
+            },
+        })
+    }
+}
+
+/// A single entry of `/proc/[pid]/smaps`: a VMA and its statistics.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SmapsEntry {
+    pub vma: Vma,
+    /// Numeric fields by name (e.g., `Rss`, `AnonHugePages`). Sizes are in kB, as reported.
+    pub fields: BTreeMap<String, u64>,
+    /// The mnemonics in the `VmFlags` field.
+    pub vm_flags: Vec<String>,
+}
+
+fn invalid_data(err: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// Parses the contents of a maps file.
+pub fn parse_maps<R: BufRead>(reader: R) -> io::Result<Vec<Vma>> {
+    reader
+        .lines()
+        .map(|line| line?.parse().map_err(invalid_data))
+        .collect()
+}
+
+/// Parses the contents of an smaps file.
+pub fn parse_smaps<R: BufRead>(reader: R) -> io::Result<Vec<SmapsEntry>> {
+    let mut entries: Vec<SmapsEntry> = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+
+        // Field lines start with `Name:`. Everything else starts a new VMA.
+        let Some((name, value)) = line
+            .split_once(char::is_whitespace)
+            .and_then(|(name, value)| Some((name.strip_suffix(':')?, value.trim())))
+        else {
+            entries.push(SmapsEntry {
+                vma: line.parse().map_err(invalid_data)?,
+                fields: BTreeMap::new(),
+                vm_flags: Vec::new(),
+            });
+            continue;
+        };
+
+        let entry = entries
+            .last_mut()
+            .ok_or_else(|| invalid_data(format!("smaps field before any VMA: {}", line)))?;
+
+        if name == "VmFlags" {
+            entry.vm_flags = value.split_whitespace().map(str::to_owned).collect();
+        } else if let Some(Ok(value)) = value.split_whitespace().next().map(str::parse) {
+            entry.fields.insert(name.to_owned(), value);
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Reads `/proc/[pid]/maps`.
+pub fn read_maps(pid: u32) -> io::Result<Vec<Vma>> {
+    parse_maps(BufReader::new(File::open(format!("/proc/{pid}/maps"))?))
+}
+
+/// Reads `/proc/[pid]/smaps`.
+pub fn read_smaps(pid: u32) -> io::Result<Vec<SmapsEntry>> {
+    parse_smaps(BufReader::new(File::open(format!("/proc/{pid}/smaps"))?))
+}
+
+/// The most pages `VmaPagesIterator` reads at once.
+pub const VMA_CHUNK_PAGES: usize = 1 << 16;
+
+/// Walks the VMAs of a process, producing the pagemap entries of each VMA.
+///
+/// Large VMAs are produced in chunks of at most `VMA_CHUNK_PAGES` pages, each paired with the
+/// same `Vma`, so memory use is bounded no matter how large the VMAs are.
+pub struct VmaPagesIterator<K: PageMappy> {
+    pagemap: PageMapFile<K>,
+    vmas: std::vec::IntoIter<Vma>,
+    /// The VMA being read, and the virtual address to continue from.
+    current: Option<(Vma, usize)>,
+}
+
+impl<K: PageMappy> VmaPagesIterator<K> {
+    pub fn new(pagemap: PageMapFile<K>, vmas: Vec<Vma>) -> Self {
+        VmaPagesIterator {
+            pagemap,
+            vmas: vmas.into_iter(),
+            current: None,
+        }
+    }
+
+    /// Walks all VMAs of the process with the given pid.
+    pub fn open(pid: u32) -> io::Result<Self> {
+        Ok(Self::new(PageMapFile::open(pid)?, read_maps(pid)?))
+    }
+}
+
+impl<K: PageMappy> Iterator for VmaPagesIterator<K> {
+    type Item = io::Result<(Vma, Vec<(usize, PageMapPage<K>)>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (vma, start) = match self.current.take() {
+            Some(current) => current,
+            None => {
+                let vma = self.vmas.next()?;
+                let start = vma.start;
+                (vma, start)
+            }
+        };
+
+        let chunk_bytes = VMA_CHUNK_PAGES * self.pagemap.page_size();
+        let end = vma.end.min(start.saturating_add(chunk_bytes));
+        if end < vma.end {
+            self.current = Some((vma.clone(), end));
+        }
+
+        Some(
+            self.pagemap
+                .read_range(start..end)
+                .collect::<Result<_, _>>()
+                .map(|pages| (vma, pages))
+                .map_err(Into::into),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::pagemap::PM6_0_0;
+
+    #[test]
+    fn maps_lines_are_parsed() {
+        let vma: Vma = "7f3a1c000000-7f3a1c021000 r-xp 0001a000 fd:01 1835023 \
+                        /home/user/My Documents/lib foo.so (deleted)"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            vma,
+            Vma {
+                start: 0x7f3a1c000000,
+                end: 0x7f3a1c021000,
+                perms: Perms {
+                    read: true,
+                    write: false,
+                    exec: true,
+                    shared: false,
+                },
+                offset: 0x1a000,
+                dev: (0xfd, 0x01),
+                inode: 1835023,
+                path: Some("/home/user/My Documents/lib foo.so (deleted)".to_owned()),
+            }
+        );
+        assert_eq!(vma.len(), 0x21000);
+        assert_eq!(vma.perms.to_string(), "r-xp");
+
+        let maps = "\
+55d0c8a2e000-55d0c8a4f000 rw-p 00000000 00:00 0                          [heap]
+7f3a1c200000-7f3a1c400000 rw-s 00000000 00:05 4096                       /dev/zero (deleted)
+7f3a1c400000-7f3a1c402000 rw-p 00000000 00:00 0 \n\
+7ffd4a5e1000-7ffd4a602000 rw-p 00000000 00:00 0                          [stack]
+";
+        let vmas = parse_maps(maps.as_bytes()).unwrap();
+        let vmas: Vec<_> = vmas
+            .iter()
+            .map(|vma| (vma.perms.to_string(), vma.inode, vma.path.as_deref()))
+            .collect();
+        assert_eq!(
+            vmas,
+            [
+                ("rw-p".to_owned(), 0, Some("[heap]")),
+                ("rw-s".to_owned(), 4096, Some("/dev/zero (deleted)")),
+                ("rw-p".to_owned(), 0, None),
+                ("rw-p".to_owned(), 0, Some("[stack]")),
+            ]
+        );
+
+        assert!("7f3a1c000000 r-xp 00000000 fd:01 0".parse::<Vma>().is_err());
+        assert!("7f3a1c000000-7f3a1c021000 r-x 00000000 fd:01 0"
+            .parse::<Vma>()
+            .is_err());
+        assert!("7f3a1c000000-7f3a1c021000 r-xp 00000000 fd01 0"
+            .parse::<Vma>()
+            .is_err());
+        assert!("7f3a1c000000-7f3a1c021000 r-xp".parse::<Vma>().is_err());
+    }
+
+    #[test]
+    fn smaps_blocks_are_parsed() {
+        let smaps = "\
+55d0c8a2e000-55d0c8a4f000 rw-p 00000000 00:00 0                          [heap]
+Size:                132 kB
+KernelPageSize:        4 kB
+Rss:                  12 kB
+Anonymous:            12 kB
+AnonHugePages:         0 kB
+THPeligible:    0
+ProtectionKey:         0
+VmFlags: rd wr mr mw me ac sd
+7f3a1c000000-7f3a1c021000 r-xp 0001a000 fd:01 1835023                    /opt/my app/lib.so
+Size:                132 kB
+Rss:                  64 kB
+VmFlags: rd ex mr mw me sd
+";
+        let entries = parse_smaps(smaps.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].vma.path.as_deref(), Some("[heap]"));
+        assert_eq!(entries[0].fields["Size"], 132);
+        assert_eq!(entries[0].fields["Rss"], 12);
+        assert_eq!(entries[0].fields["THPeligible"], 0);
+        assert_eq!(entries[0].fields.len(), 7);
+        assert_eq!(
+            entries[0].vm_flags,
+            ["rd", "wr", "mr", "mw", "me", "ac", "sd"]
+        );
+
+        assert_eq!(entries[1].vma.path.as_deref(), Some("/opt/my app/lib.so"));
+        assert_eq!(entries[1].fields["Rss"], 64);
+        assert_eq!(entries[1].vm_flags, ["rd", "ex", "mr", "mw", "me", "sd"]);
+
+        assert!(parse_smaps("Rss: 4 kB\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn vma_pages_are_chunked() {
+        let npages = 3 * VMA_CHUNK_PAGES + 100;
+        let mut file = tempfile::tempfile().unwrap();
+        let entries: Vec<u8> = (0..npages as u64).flat_map(u64::to_ne_bytes).collect();
+        file.write_all(&entries).unwrap();
+
+        let pagemap = PageMapFile::<PM6_0_0::Flags>::new(file);
+        let page_size = pagemap.page_size();
+        let maps = format!(
+            "{:x}-{:x} rw-p 00000000 00:00 0\n{:x}-{:x} r--p 00000000 00:00 0 [vdso]\n",
+            page_size,
+            (3 * VMA_CHUNK_PAGES + 1) * page_size,
+            (3 * VMA_CHUNK_PAGES + 10) * page_size,
+            (3 * VMA_CHUNK_PAGES + 12) * page_size,
+        );
+        let vmas = parse_maps(maps.as_bytes()).unwrap();
+
+        let chunks: Vec<_> = VmaPagesIterator::new(pagemap, vmas.clone())
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        let lens: Vec<_> = chunks.iter().map(|(_, pages)| pages.len()).collect();
+        assert_eq!(lens, [VMA_CHUNK_PAGES, VMA_CHUNK_PAGES, VMA_CHUNK_PAGES, 2]);
+        assert!(chunks[..3].iter().all(|(vma, _)| *vma == vmas[0]));
+        assert_eq!(chunks[3].0, vmas[1]);
+
+        let big: Vec<_> = chunks[..3]
+            .iter()
+            .flat_map(|(_, pages)| pages.iter())
+            .map(|(vaddr, page)| (*vaddr / page_size, page.as_u64()))
+            .collect();
+        let expected: Vec<_> = (1..3 * VMA_CHUNK_PAGES + 1)
+            .map(|page| (page, page as u64))
+            .collect();
+        assert_eq!(big, expected);
+    }
+}
@@ -0,0 +1,412 @@
+//! Tools for reasoning about memory hotplug, via the memory blocks in `/sys/devices/system/memory`.
+
+use std::{io, ops::Range, path::Path};
+
+use crate::{
+    kpageflags::{Flaggy, KPageFlags},
+    topology::memory_block_size,
+};
+
+/// The directory... `/sys/devices/system/memory`.
+pub const MEMORY_ROOT: &str = "/sys/devices/system/memory";
+
+/// The state of a memory block, from its `state` file.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub enum BlockState {
+    Online,
+    Offline,
+    GoingOffline,
+    /// Anything else the kernel might report.
+    Other(String),
+}
+
+impl BlockState {
+    fn parse(s: &str) -> Self {
+        match s {
+            "online" => BlockState::Online,
+            "offline" => BlockState::Offline,
+            "going-offline" => BlockState::GoingOffline,
+            other => BlockState::Other(other.to_owned()),
+        }
+    }
+}
+
+/// A memory block, i.e., the unit of memory hotplug.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct MemoryBlock {
+    /// `N` in `memoryN`.
+    pub index: u64,
+    pub pfns: Range<u64>,
+    pub state: BlockState,
+    /// The NUMA node of the block, if the kernel says.
+    pub node: Option<u32>,
+    /// The `removable` file, which is missing on some kernels and always `true` on others.
+    pub removable: Option<bool>,
+    /// For an online block, the zone it is in. For an offline block, the zones it could be onlined
+    /// to. Empty if the kernel reports `none`, e.g., because an online block spans several zones,
+    /// which prevents it from being offlined.
+    pub valid_zones: Vec<String>,
+}
+
+impl MemoryBlock {
+    /// Predicts whether offlining this block would succeed, given the usage of its pages.
+    pub fn offlinability(&self, usage: &BlockUsage) -> Offlinability {
+        if self.state == BlockState::Offline {
+            Offlinability::AlreadyOffline
+        } else if self.removable == Some(false)
+            || (self.state == BlockState::Online && self.valid_zones.is_empty())
+            || usage.unmovable > 0
+        {
+            Offlinability::Unlikely
+        } else if usage.other > 0 {
+            Offlinability::Maybe
+        } else {
+            Offlinability::Likely
+        }
+    }
+}
+
+/// Reads all memory blocks of this machine, sorted by index.
+pub fn read_memory_blocks() -> io::Result<Vec<MemoryBlock>> {
+    let block_pages = memory_block_size()? / crate::page_size() as u64;
+    read_memory_blocks_from(MEMORY_ROOT, block_pages)
+}
+
+/// Reads the `memoryN` directories under `root`, e.g., `MEMORY_ROOT`, where each block has
+/// `block_pages` pages.
+pub fn read_memory_blocks_from<P: AsRef<Path>>(
+    root: P,
+    block_pages: u64,
+) -> io::Result<Vec<MemoryBlock>> {
+    let mut blocks = Vec::new();
+
+    for entry in std::fs::read_dir(root)? {
+        let entry = entry?;
+        let Some(index) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix("memory"))
+            .and_then(|index| index.parse::<u64>().ok())
+        else {
+            continue;
+        };
+
+        let dir = entry.path();
+        let read = |name: &str| -> io::Result<Option<String>> {
+            match std::fs::read_to_string(dir.join(name)) {
+                Ok(s) => Ok(Some(s.trim().to_owned())),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err),
+            }
+        };
+
+        // The node is only exposed as a `nodeN` link.
+        let mut node = None;
+        for entry in std::fs::read_dir(&dir)? {
+            if let Some(n) = entry?
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("node"))
+                .and_then(|n| n.parse().ok())
+            {
+                node = Some(n);
+            }
+        }
+
+        blocks.push(MemoryBlock {
+            index,
+            pfns: index * block_pages..(index + 1) * block_pages,
+            state: BlockState::parse(&read("state")?.unwrap_or_default()),
+            node,
+            removable: read("removable")?.map(|removable| removable == "1"),
+            valid_zones: read("valid_zones")?
+                .unwrap_or_default()
+                .split_whitespace()
+                .filter(|zone| *zone != "none")
+                .map(str::to_owned)
+                .collect(),
+        });
+    }
+    blocks.sort_by_key(|block| block.index);
+
+    Ok(blocks)
+}
+
+/// Counts of the pages in a memory block by how they affect offlining. Each page is counted in
+/// exactly one category, in the order below.
+#[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub struct BlockUsage {
+    pub total: u64,
+    /// `NOPAGE` pages, i.e., holes.
+    pub nopage: u64,
+    /// `OFFLINE` pages, e.g., logically offlined by a balloon driver.
+    pub offline: u64,
+    /// Free pages, i.e., `BUDDY` pages and the rest of their free blocks (see `FreeBlock`).
+    pub free: u64,
+    /// Pages that can't be migrated: `SLAB`, `RESERVED`, and `PGTABLE`.
+    pub unmovable: u64,
+    /// Pages on the LRU, which can usually be migrated.
+    pub movable: u64,
+    /// Everything else, e.g., kernel allocations without any telling flags. These may or may not
+    /// be movable.
+    pub other: u64,
+}
+
+impl BlockUsage {
+    /// Counts the page at `pfn` with the given flags. `free_block` tracks the free block the page
+    /// may be in, and must have been passed the previous pages in order of PFN.
+    pub fn add<K: Flaggy>(&mut self, pfn: u64, flags: KPageFlags<K>, free_block: &mut FreeBlock) {
+        self.total += 1;
+
+        let free = free_block.is_free(pfn, flags);
+        let counter = if flags.all(K::NOPAGE) {
+            &mut self.nopage
+        } else if K::OFFLINE.is_some_and(|offline| flags.all(offline)) {
+            &mut self.offline
+        } else if free {
+            &mut self.free
+        } else if flags.any(K::SLAB | K::RESERVED)
+            || K::PGTABLE.is_some_and(|pgtable| flags.all(pgtable))
+        {
+            &mut self.unmovable
+        } else if flags.all(K::LRU) {
+            &mut self.movable
+        } else {
+            &mut self.other
+        };
+        *counter += 1;
+    }
+
+    /// Adds all counts from `other` to `self`.
+    pub fn merge(&mut self, other: &Self) {
+        self.total += other.total;
+        self.nopage += other.nopage;
+        self.offline += other.offline;
+        self.free += other.free;
+        self.unmovable += other.unmovable;
+        self.movable += other.movable;
+        self.other += other.other;
+    }
+}
+
+/// Counts consecutive pages, starting at PFN 0 (or any other multiple of `1 << MAX_ORDER`, like
+/// the start of a memory block).
+impl<K: Flaggy> FromIterator<KPageFlags<K>> for BlockUsage {
+    fn from_iter<I: IntoIterator<Item = KPageFlags<K>>>(iter: I) -> Self {
+        let mut usage = Self::default();
+        let mut free_block = FreeBlock::default();
+        for (pfn, flags) in (0..).zip(iter) {
+            usage.add(pfn, flags, &mut free_block);
+        }
+        usage
+    }
+}
+
+/// The largest order of a free block in the buddy allocator, i.e., `MAX_PAGE_ORDER` (`MAX_ORDER - 1`
+/// before 6.4) on most configurations.
+pub const MAX_ORDER: u32 = 10;
+
+/// The free block of the buddy allocator that consecutive pages may be in. The kernel only sets
+/// `BUDDY` on the first page of a free block, and the other pages have no flags at all. Since the
+/// order of the block isn't reported, pages without flags count as free up to the largest block
+/// that could start at the `BUDDY` page: blocks of order `n` are aligned to `1 << n` pages, and `n`
+/// is at most `MAX_ORDER`.
+#[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub struct FreeBlock {
+    /// The end of the largest block that the last `BUDDY` page could start, if we're in it.
+    end: Option<u64>,
+}
+
+impl FreeBlock {
+    /// Returns whether the page at `pfn` with the given flags is free, and moves on to it. Pages
+    /// must be passed in order of PFN; start over with a new `FreeBlock` after a gap.
+    pub fn is_free<K: Flaggy>(&mut self, pfn: u64, flags: KPageFlags<K>) -> bool {
+        if flags.all(K::BUDDY) {
+            let order = pfn.trailing_zeros().min(MAX_ORDER);
+            self.end = Some(pfn + (1 << order));
+            true
+        } else if flags == KPageFlags::empty() && self.end.is_some_and(|end| pfn < end) {
+            true
+        } else {
+            self.end = None;
+            false
+        }
+    }
+}
+
+/// A prediction of whether offlining a memory block would succeed.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, PartialOrd, Ord)]
+pub enum Offlinability {
+    /// All pages are free, movable, or already offline.
+    Likely,
+    /// Some pages have no flags that tell whether they can be migrated.
+    Maybe,
+    /// Some pages are unmovable, or the kernel says the block can't be removed.
+    Unlikely,
+    AlreadyOffline,
+}
+
+impl std::fmt::Display for Offlinability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Offlinability::Likely => "likely",
+            Offlinability::Maybe => "maybe",
+            Offlinability::Unlikely => "unlikely",
+            Offlinability::AlreadyOffline => "offline",
+        })
+    }
+}
+
+/// Classifies the pages of each block. `flags` are the flags of consecutive pages starting at PFN
+/// `start_pfn`, e.g., from a `KPageFlagsIterator`, and `blocks` must be sorted by PFN. Blocks that
+/// `flags` does not cover have no pages counted.
+pub fn classify_blocks<K: Flaggy>(
+    blocks: &[MemoryBlock],
+    flags: impl IntoIterator<Item = KPageFlags<K>>,
+    start_pfn: u64,
+) -> Vec<BlockUsage> {
+    let mut usages = vec![BlockUsage::default(); blocks.len()];
+    let mut blocks = blocks.iter().zip(usages.iter_mut()).peekable();
+    let mut free_block = FreeBlock::default();
+
+    for (pfn, flags) in (start_pfn..).zip(flags) {
+        // Skip blocks entirely before this page.
+        while blocks.next_if(|(block, _)| block.pfns.end <= pfn).is_some() {}
+
+        match blocks.peek_mut() {
+            Some((block, usage)) if block.pfns.contains(&pfn) => {
+                usage.add(pfn, flags, &mut free_block)
+            }
+            Some(_) => free_block = FreeBlock::default(),
+            None => break,
+        }
+    }
+
+    usages
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use super::*;
+    use crate::kpageflags::{KPageFlagsIterator, KPageFlagsReader, KPF6_0_0};
+
+    type Flags = KPF6_0_0::Flags;
+
+    /// Parses a stream of raw flags like the kernel would return them.
+    fn pages(flags: &[Flags]) -> Vec<KPageFlags<Flags>> {
+        let bytes: Vec<u8> = flags
+            .iter()
+            .flat_map(|flags| u64::from(*flags).to_ne_bytes())
+            .collect();
+        KPageFlagsIterator::new(KPageFlagsReader::new(BufReader::new(bytes.as_slice())), &[])
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn block(index: u64, pfns: Range<u64>) -> MemoryBlock {
+        MemoryBlock {
+            index,
+            pfns,
+            state: BlockState::Online,
+            node: Some(0),
+            removable: Some(true),
+            valid_zones: vec!["Normal".to_owned()],
+        }
+    }
+
+    #[test]
+    fn free_buddy_tails_are_free() {
+        let none = Flags::empty();
+        let pages = pages(&[
+            // An order-2 free block.
+            KPF6_0_0::Buddy,
+            none,
+            none,
+            none,
+            KPF6_0_0::Lru,
+            // Not after a free block.
+            none,
+            KPF6_0_0::Slab,
+            none,
+            // An order-1 free block.
+            KPF6_0_0::Buddy,
+            none,
+        ]);
+
+        let usage: BlockUsage = pages.into_iter().collect();
+        assert_eq!(
+            usage,
+            BlockUsage {
+                total: 10,
+                free: 6,
+                unmovable: 1,
+                movable: 1,
+                other: 2,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn free_blocks_are_bounded() {
+        let max_pages = 1 << MAX_ORDER;
+
+        // Flagless pages past the largest possible free block are kernel allocations.
+        let mut flags = vec![KPF6_0_0::Buddy];
+        flags.resize(2000, Flags::empty());
+        let usage: BlockUsage = pages(&flags).into_iter().collect();
+        assert_eq!(usage.free, max_pages);
+        assert_eq!(usage.other, 2000 - max_pages);
+
+        // A free block at PFN 12 has at most 4 pages.
+        let mut flags = vec![KPF6_0_0::Lru; 12];
+        flags.push(KPF6_0_0::Buddy);
+        flags.resize(30, Flags::empty());
+        let usage: BlockUsage = pages(&flags).into_iter().collect();
+        assert_eq!(usage.free, 4);
+        assert_eq!(usage.other, 14);
+    }
+
+    #[test]
+    fn blocks_are_classified() {
+        let none = Flags::empty();
+        let pages = pages(&[
+            KPF6_0_0::Buddy,
+            none,
+            none,
+            none,
+            // Skipped, between the blocks.
+            KPF6_0_0::Lru,
+            KPF6_0_0::Lru,
+            none,
+            none,
+            none,
+            none,
+            KPF6_0_0::Buddy,
+            none,
+        ]);
+
+        let blocks = [block(0, 0..4), block(2, 8..12)];
+        let usages = classify_blocks(&blocks, pages, 0);
+        assert_eq!(
+            usages,
+            [
+                BlockUsage {
+                    total: 4,
+                    free: 4,
+                    ..Default::default()
+                },
+                BlockUsage {
+                    total: 4,
+                    free: 2,
+                    other: 2,
+                    ..Default::default()
+                },
+            ]
+        );
+        assert_eq!(blocks[0].offlinability(&usages[0]), Offlinability::Likely);
+        assert_eq!(blocks[1].offlinability(&usages[1]), Offlinability::Maybe);
+    }
+}
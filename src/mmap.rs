@@ -0,0 +1,133 @@
+//! Zero-copy access to dumps of `/proc/kpageflags` and friends via `mmap`. Requires the `mmap`
+//! feature.
+//!
+//! This is only useful for saved dumps and snapshots: the files in `/proc` cannot be mapped.
+
+use std::{fs::File, io, marker::PhantomData, ops::Deref, path::Path};
+
+use memmap2::{Mmap, MmapOptions};
+
+use crate::{compress::looks_compressed, Error, FileReadable};
+
+/// Casts `bytes` as a slice of records, checking that it is suitably aligned and contains a
+/// whole number of records.
+pub fn cast_slice<T: FileReadable>(bytes: &[u8]) -> Result<&[T], Error> {
+    let size = std::mem::size_of::<T>();
+
+    let trailing_bytes = bytes.len() % size;
+    if trailing_bytes != 0 {
+        return Err(if looks_compressed(bytes) {
+            Error::Compressed
+        } else {
+            Error::Truncated { trailing_bytes }
+        });
+    }
+
+    if bytes.as_ptr().align_offset(std::mem::align_of::<T>()) != 0 {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "records are not aligned in memory",
+        )));
+    }
+
+    // Safe because `FileReadable` types can be cast from any bytes, and we checked the length and
+    // alignment above.
+    Ok(unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const T, bytes.len() / size) })
+}
+
+/// A memory-mapped file of `FileReadable` records, which derefs to `&[T]`.
+///
+/// The file must not be modified while it is mapped; see `memmap2::Mmap`.
+pub struct FileReadableMmap<T: FileReadable> {
+    mmap: Mmap,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: FileReadable> FileReadableMmap<T> {
+    /// Maps the whole file.
+    pub fn new(file: &File) -> io::Result<Self> {
+        let len = file.metadata()?.len();
+        Self::with_range(file, 0, len)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(&File::open(path)?)
+    }
+
+    /// Maps `len` bytes of the file starting at byte `offset`, e.g., one section of a snapshot.
+    /// `offset` should be a multiple of the alignment of `T`. Fails if the range extends past the
+    /// end of the file, since touching such a mapping would crash the process.
+    pub fn with_range(file: &File, offset: u64, len: u64) -> io::Result<Self> {
+        let file_len = file.metadata()?.len();
+        let past_end = || {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "cannot map {} bytes at offset {} of a {}-byte file",
+                    len, offset, file_len
+                ),
+            )
+        };
+        if offset.checked_add(len).is_none_or(|end| end > file_len) {
+            return Err(past_end());
+        }
+        let len = usize::try_from(len).map_err(|_| past_end())?;
+
+        let mmap = unsafe { MmapOptions::new().offset(offset).len(len).map(file)? };
+        if mmap.len() != len {
+            return Err(past_end());
+        }
+
+        // Validate once up front, so that `as_slice` can't fail.
+        cast_slice::<T>(&mmap)?;
+
+        Ok(FileReadableMmap {
+            mmap,
+            _phantom: PhantomData,
+        })
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        cast_slice(&self.mmap).unwrap()
+    }
+}
+
+impl<T: FileReadable> Deref for FileReadableMmap<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::kpagecount::KPageCount;
+
+    #[test]
+    fn maps_a_range() {
+        let mut file = tempfile::tempfile().unwrap();
+        let bytes: Vec<u8> = (0..16u64).flat_map(u64::to_ne_bytes).collect();
+        file.write_all(&bytes).unwrap();
+
+        let mmap = FileReadableMmap::<KPageCount>::with_range(&file, 8 * 4, 8 * 8).unwrap();
+        let counts: Vec<_> = mmap.iter().map(|count| count.as_u64()).collect();
+        assert_eq!(counts, (4..12).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn rejects_ranges_past_the_end() {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&[0; 64]).unwrap();
+
+        for (offset, len) in [(0, 72), (64, 8), (8, u64::MAX), (u64::MAX - 7, 8)] {
+            let err = FileReadableMmap::<KPageCount>::with_range(&file, offset, len)
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+}
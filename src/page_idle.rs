@@ -0,0 +1,230 @@
+//! Tools for idle page tracking via `/sys/kernel/mm/page_idle/bitmap`.
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    ops::Range,
+    os::unix::fs::FileExt,
+};
+
+use crate::kpageflags::{Flaggy, KPageFlags, KPageFlagsFile};
+
+/// The file path... `/sys/kernel/mm/page_idle/bitmap`.
+pub const PAGE_IDLE_BITMAP_PATH: &str = "/sys/kernel/mm/page_idle/bitmap";
+
+/// The number of PFNs covered by each word of the bitmap.
+const PFNS_PER_WORD: u64 = 64;
+
+/// Returns the range of bitmap words covering the given range of PFNs.
+fn words(range: &Range<u64>) -> Range<u64> {
+    range.start / PFNS_PER_WORD..range.end.div_ceil(PFNS_PER_WORD)
+}
+
+/// Returns an `InvalidInput` error for a range of PFNs that ends before it starts.
+fn check_range(range: &Range<u64>) -> io::Result<()> {
+    if range.end < range.start {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "invalid range of PFNs: {:#x}..{:#x}",
+                range.start, range.end
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Marks and scans pages using the page_idle bitmap.
+///
+/// The typical use is to `mark` a range of PFNs idle, wait, and then `scan` the same range: pages
+/// that were accessed in the meantime are no longer idle. The kernel only tracks LRU pages, so
+/// other pages are never reported.
+pub struct IdleTracker {
+    file: File,
+}
+
+impl IdleTracker {
+    pub fn new(file: File) -> Self {
+        IdleTracker { file }
+    }
+
+    /// Opens `/sys/kernel/mm/page_idle/bitmap` for reading and writing.
+    pub fn open() -> io::Result<Self> {
+        Ok(Self::new(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(PAGE_IDLE_BITMAP_PATH)?,
+        ))
+    }
+
+    /// Marks all pages in the given range of PFNs idle. Fails with `InvalidInput` if the range ends
+    /// before it starts, like `read` and `scan`.
+    pub fn mark(&self, range: Range<u64>) -> io::Result<()> {
+        check_range(&range)?;
+
+        // The kernel only sets the idle bit for bits that are set in the written words, so we can
+        // write partial words at the ends of the range.
+        let buf: Vec<u8> = words(&range)
+            .flat_map(|word| {
+                let first = word * PFNS_PER_WORD;
+                let mask = (first..first + PFNS_PER_WORD)
+                    .enumerate()
+                    .filter(|(_, pfn)| range.contains(pfn))
+                    .fold(0u64, |mask, (bit, _)| mask | 1 << bit);
+                mask.to_ne_bytes()
+            })
+            .collect();
+
+        self.file.write_all_at(
+            &buf,
+            words(&range).start * std::mem::size_of::<u64>() as u64,
+        )
+    }
+
+    /// Returns `true` for each page in the given range of PFNs that is idle. The result is
+    /// truncated if the range extends past the end of physical memory.
+    pub fn read(&self, range: Range<u64>) -> io::Result<Vec<bool>> {
+        check_range(&range)?;
+        let words = words(&range);
+        let mut buf = vec![0u8; (words.end - words.start) as usize * std::mem::size_of::<u64>()];
+
+        let mut total_bytes_read = 0;
+        while total_bytes_read < buf.len() {
+            match self.file.read_at(
+                &mut buf[total_bytes_read..],
+                words.start * std::mem::size_of::<u64>() as u64 + total_bytes_read as u64,
+            ) {
+                // Reached EOF
+                Ok(0) => break,
+                Ok(n) => total_bytes_read += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        buf.truncate(total_bytes_read - total_bytes_read % std::mem::size_of::<u64>());
+
+        let bits = buf
+            .chunks_exact(std::mem::size_of::<u64>())
+            .flat_map(|word| {
+                let word = u64::from_ne_bytes(word.try_into().unwrap());
+                (0..PFNS_PER_WORD).map(move |bit| word & (1 << bit) != 0)
+            });
+
+        Ok(bits
+            .skip((range.start - words.start * PFNS_PER_WORD) as usize)
+            .take((range.end - range.start) as usize)
+            .collect())
+    }
+
+    /// Returns `(pfn, flags, idle)` for each LRU page in the given range of PFNs.
+    pub fn scan<K: Flaggy>(
+        &self,
+        range: Range<u64>,
+        kpageflags: &KPageFlagsFile<K>,
+    ) -> io::Result<Vec<(u64, KPageFlags<K>, bool)>> {
+        let idle = self.read(range.clone())?;
+        let flags = kpageflags.read_range(range.clone())?;
+
+        Ok(range
+            .zip(flags)
+            .zip(idle)
+            .filter(|((_, flags), _)| flags.all(K::LRU))
+            .map(|((pfn, flags), idle)| (pfn, flags, idle))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::kpageflags::KPF6_0_0;
+
+    /// A tracker over a bitmap file of `words` empty words.
+    fn tracker(words: usize) -> IdleTracker {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&vec![0; words * 8]).unwrap();
+        IdleTracker::new(file)
+    }
+
+    fn idle_pfns(tracker: &IdleTracker, range: Range<u64>) -> Vec<u64> {
+        let idle = tracker.read(range.clone()).unwrap();
+        range
+            .zip(idle)
+            .filter(|(_, idle)| *idle)
+            .map(|(pfn, _)| pfn)
+            .collect()
+    }
+
+    #[test]
+    fn unaligned_ranges_are_marked() {
+        let tracker = tracker(4);
+
+        tracker.mark(70..130).unwrap();
+        assert_eq!(idle_pfns(&tracker, 0..256), (70..130).collect::<Vec<_>>());
+        tracker.mark(3..5).unwrap();
+        assert_eq!(idle_pfns(&tracker, 0..64), [3, 4]);
+
+        // Reads that don't start or end on a word.
+        assert_eq!(
+            tracker.read(65..72).unwrap(),
+            [false, false, false, false, false, true, true]
+        );
+        assert_eq!(tracker.read(129..131).unwrap(), [true, false]);
+        assert!(tracker.read(4..4).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reads_are_truncated_at_the_end() {
+        let tracker = tracker(2);
+        tracker.mark(100..128).unwrap();
+
+        let idle = tracker.read(120..300).unwrap();
+        assert_eq!(idle.len(), 8);
+        assert!(idle.iter().all(|idle| *idle));
+    }
+
+    #[test]
+    fn backwards_ranges_are_invalid() {
+        let tracker = tracker(2);
+        let invalid = io::ErrorKind::InvalidInput;
+
+        #[allow(clippy::reversed_empty_ranges)]
+        let range = 70..10;
+        assert_eq!(tracker.mark(range.clone()).unwrap_err().kind(), invalid);
+        assert_eq!(tracker.read(range.clone()).unwrap_err().kind(), invalid);
+
+        let kpageflags = KPageFlagsFile::<KPF6_0_0::Flags>::new(tempfile::tempfile().unwrap());
+        assert_eq!(
+            tracker.scan(range, &kpageflags).unwrap_err().kind(),
+            invalid
+        );
+    }
+
+    #[test]
+    fn only_lru_pages_are_scanned() {
+        let tracker = tracker(2);
+        tracker.mark(60..66).unwrap();
+
+        let mut file = tempfile::tempfile().unwrap();
+        let flags: Vec<u8> = (0..128u64)
+            .map(|pfn| match pfn % 3 {
+                0 => u64::from(KPF6_0_0::Lru),
+                _ => u64::from(KPF6_0_0::Slab),
+            })
+            .flat_map(u64::to_ne_bytes)
+            .collect();
+        file.write_all(&flags).unwrap();
+        let kpageflags = KPageFlagsFile::<KPF6_0_0::Flags>::new(file);
+
+        let scanned: Vec<_> = tracker
+            .scan(58..68, &kpageflags)
+            .unwrap()
+            .into_iter()
+            .map(|(pfn, _, idle)| (pfn, idle))
+            .collect();
+        assert_eq!(scanned, [(60, true), (63, true), (66, false)]);
+    }
+}
@@ -6,7 +6,16 @@ use std::{
     str::FromStr,
 };
 
-use crate::{FileReadable, FileReadableReader};
+use crate::FileReadable;
+
+mod flags;
+mod read;
+
+pub use flags::{
+    PM3_10_0, PM4_15_0, PM5_0_8, PM5_13_0, PM5_15_0, PM5_17_0, PM5_19_0, PM5_4_0, PM6_0_0,
+    PM6_12_0, PM6_1_0, PM6_6_0,
+};
+pub use read::{PageMapFile, PageMapReader};
 
 /// All the different pagemap implementations are `PageMappy`.
 pub trait PageMappy:
@@ -31,6 +40,11 @@ pub trait PageMappy:
 
     /// Returns a mask for the location information of the page.
     fn location_mask() -> u64;
+
+    /// Returns a mask for the page shift, on kernels that report it (before 4.2).
+    fn page_shift_mask() -> Option<u64> {
+        None
+    }
 }
 
 /// Represents the flags for a single virtual page in the address space of a process as given by
@@ -69,6 +83,26 @@ impl<K: PageMappy> PageMapPage<K> {
         let shift = mask.trailing_zeros();
         (self.0 & mask) >> shift
     }
+
+    /// The PFN of the page, if it is present.
+    pub fn pfn(self) -> Option<u64> {
+        self.has(K::PRESENT).then(|| self.location())
+    }
+
+    /// The swap type of the page, if it is swapped (bits 0-4 of the location).
+    pub fn swap_type(self) -> Option<u64> {
+        self.has(K::SWAPPED).then(|| self.location() & 0x1f)
+    }
+
+    /// The swap offset of the page, if it is swapped (bits 5-54 of the location).
+    pub fn swap_offset(self) -> Option<u64> {
+        self.has(K::SWAPPED).then(|| self.location() >> 5)
+    }
+
+    /// The page shift (i.e., log2 of the page size), on kernels that report it.
+    pub fn page_shift(self) -> Option<u64> {
+        K::page_shift_mask().map(|mask| (self.0 & mask) >> mask.trailing_zeros())
+    }
 }
 
 unsafe impl<K: PageMappy> FileReadable for PageMapPage<K> {}
@@ -101,7 +135,8 @@ impl<K: PageMappy> std::fmt::Display for PageMapPage<K> {
             }
         }
 
-        let invalid_bits = self.0 & !K::valid_mask();
+        let fields = K::location_mask() | K::page_shift_mask().unwrap_or(0);
+        let invalid_bits = self.0 & !K::valid_mask() & !fields;
         if invalid_bits != 0 {
             write!(f, "INVALID BITS: {invalid_bits:X?}")?;
         }
@@ -110,7 +145,98 @@ impl<K: PageMappy> std::fmt::Display for PageMapPage<K> {
     }
 }
 
-/// Wrapper around a `Read` type that for the `/proc/[pid]/pagemap` file.
-pub type PageMapReader<R, K> = FileReadableReader<R, PageMapPage<K>>;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-// TODO: implement PageMappy for a few kernels...
+    fn page<K: PageMappy>(raw: u64) -> PageMapPage<K> {
+        PageMapPage(raw, PhantomData)
+    }
+
+    /// Checks the bits that every layout has.
+    fn check_layout<K: PageMappy>(uffd_wp: bool) {
+        let present = page::<K>(1 << 63 | 0x12345);
+        assert!(present.has(K::PRESENT));
+        assert_eq!(present.pfn(), Some(0x12345));
+        assert_eq!(present.swap_type(), None);
+        assert_eq!(present.swap_offset(), None);
+
+        // Swap type 3 at offset 0x1234.
+        let swapped = page::<K>(1 << 62 | 0x1234 << 5 | 3);
+        assert!(swapped.has(K::SWAPPED));
+        assert_eq!(swapped.pfn(), None);
+        assert_eq!(swapped.swap_type(), Some(3));
+        assert_eq!(swapped.swap_offset(), Some(0x1234));
+
+        let file = page::<K>(1 << 61 | 1 << 63);
+        assert!(file.has(K::FILE_OR_SHM));
+        assert!(!file.has(K::SWAPPED));
+
+        // The location is bits 0-54.
+        assert_eq!(
+            page::<K>(1 << 63 | u64::MAX >> 9).pfn(),
+            Some((1 << 55) - 1)
+        );
+        assert_eq!(K::location_mask(), (1 << 55) - 1);
+
+        let uffd_wp_flag: Option<K> = "UffdWp".parse().ok();
+        assert_eq!(uffd_wp_flag.is_some(), uffd_wp);
+        if let Some(flag) = uffd_wp_flag {
+            assert!(page::<K>(1 << 57).has(flag));
+        }
+    }
+
+    /// Checks the flags of the layouts since 4.15, which replaced the page shift with flags.
+    fn check_flags<K: PageMappy>(uffd_wp: bool) {
+        check_layout::<K>(uffd_wp);
+
+        let page = page::<K>(1 << 55 | 1 << 56 | 1 << 63 | 42);
+        assert!(page.has(K::SOFT_DIRTY.unwrap()));
+        assert!(page.has(K::EXCLUSIVE.unwrap()));
+        assert_eq!(page.page_shift(), None);
+        assert_eq!(page.pfn(), Some(42));
+        assert_eq!(
+            page.to_string(),
+            format!(
+                "{:?} {:?} Present ",
+                K::SOFT_DIRTY.unwrap(),
+                K::EXCLUSIVE.unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn pm3_10_0_has_a_page_shift() {
+        check_layout::<PM3_10_0::Flags>(false);
+
+        // 4 KiB pages, i.e., a page shift of 12 in bits 55-60.
+        let page = page::<PM3_10_0::Flags>(1 << 63 | 12 << 55 | 42);
+        assert_eq!(page.page_shift(), Some(12));
+        assert_eq!(page.pfn(), Some(42));
+        assert_eq!(page.to_string(), "Present ");
+        assert_eq!(PM3_10_0::Flags::SOFT_DIRTY, None);
+        assert_eq!(PM3_10_0::Flags::EXCLUSIVE, None);
+        assert!("SoftDirty".parse::<PM3_10_0::Flags>().is_err());
+    }
+
+    #[test]
+    fn newer_layouts_have_flags() {
+        check_flags::<PM4_15_0::Flags>(false);
+        check_flags::<PM5_0_8::Flags>(false);
+        check_flags::<PM5_4_0::Flags>(false);
+        check_flags::<PM5_13_0::Flags>(true);
+        check_flags::<PM5_15_0::Flags>(true);
+        check_flags::<PM5_17_0::Flags>(true);
+        check_flags::<PM5_19_0::Flags>(true);
+        check_flags::<PM6_0_0::Flags>(true);
+        check_flags::<PM6_1_0::Flags>(true);
+        check_flags::<PM6_6_0::Flags>(true);
+        check_flags::<PM6_12_0::Flags>(true);
+    }
+
+    #[test]
+    fn unknown_bits_are_shown() {
+        let page = page::<PM4_15_0::Flags>(1 << 63 | 1 << 58);
+        assert_eq!(page.to_string(), "Present INVALID BITS: 400000000000000");
+    }
+}
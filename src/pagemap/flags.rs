@@ -0,0 +1,341 @@
+//! Machinery for interpretting pagemap entries on a few different kernels.
+
+/// Easier to derive `PageMappy` and a bunch of other stuff...
+///
+/// Unlike `kpf!`, the generated `Flags` type holds a bit _index_ rather than a mask, as expected by
+/// `PageMapPage`.
+macro_rules! pagemap {
+    (
+        $pmname:ident { $($name:ident = $val:literal),+ $(,)? }
+        location_mask = $loc:expr;
+        page_shift_mask = $shift:expr;
+        $($c:ident: $t:ty = $v:expr;)+
+    ) => {
+        #[allow(non_snake_case)]
+        pub mod $pmname {
+            use std::str::FromStr;
+            use crate::pagemap::PageMappy;
+
+            #[allow(dead_code)]
+            #[derive(Copy, Clone, Hash, PartialEq, PartialOrd, Eq, Ord)]
+            #[repr(transparent)]
+            pub struct Flags (u64);
+
+            $(
+                #[allow(non_upper_case_globals)]
+                pub const $name : Flags = Flags($val);
+            )+
+
+            impl FromStr for Flags {
+                type Err = String;
+
+                fn from_str(s: &str) -> Result<Self, Self::Err> {
+                    match s {
+                        $(
+                            stringify!($name) => Ok($name),
+                        )+
+
+                        other => Err(format!("unknown flag: {}", other)),
+                    }
+                }
+            }
+
+            impl PageMappy for Flags {
+                $(const $c: $t = $v;)+
+
+                fn valid(val: u64) -> bool {
+                    Self::values().contains(&val)
+                }
+
+                fn values() -> &'static [u64] {
+                    &[ $($val),* ]
+                }
+
+                fn location_mask() -> u64 {
+                    $loc
+                }
+
+                fn page_shift_mask() -> Option<u64> {
+                    $shift
+                }
+            }
+
+            impl From<Flags> for u64 {
+                fn from(pm: Flags) -> u64 {
+                    pm.0
+                }
+            }
+
+            impl From<u64> for Flags {
+                fn from(val: u64) -> Self {
+                    assert!(Self::valid(val));
+                    Flags(val)
+                }
+            }
+
+            impl std::fmt::Debug for Flags {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    $(
+                        if $name == *self {
+                            return write!(f, "{}", stringify!($name));
+                        }
+                    )+
+
+                    write!(f, "INVALID({})", self.0)
+                }
+            }
+        }
+    };
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// Actual definitions of the different layouts...
+
+// pagemap for kernel 3.10.0
+//
+// Bits 55-60 hold the page shift rather than flags. Soft-dirty and exclusive mappings did not
+// exist yet.
+pagemap! {
+    PM3_10_0 {
+        File = 61,
+        Swap = 62,
+        Present = 63,
+    }
+
+    location_mask = (1 << 55) - 1;
+    page_shift_mask = Some(0x3f << 55);
+
+    PRESENT: Self = Present;
+    SWAPPED: Self = Swap;
+    FILE_OR_SHM: Self = File;
+    EXCLUSIVE: Option<Self> = None;
+    SOFT_DIRTY: Option<Self> = None;
+}
+
+// pagemap for kernel 4.15.0
+pagemap! {
+    PM4_15_0 {
+        SoftDirty = 55,
+        MmapExclusive = 56,
+        File = 61,
+        Swap = 62,
+        Present = 63,
+    }
+
+    location_mask = (1 << 55) - 1;
+    page_shift_mask = None;
+
+    PRESENT: Self = Present;
+    SWAPPED: Self = Swap;
+    FILE_OR_SHM: Self = File;
+    EXCLUSIVE: Option<Self> = Some(MmapExclusive);
+    SOFT_DIRTY: Option<Self> = Some(SoftDirty);
+}
+
+// pagemap for kernel 5.0.8
+pagemap! {
+    PM5_0_8 {
+        SoftDirty = 55,
+        MmapExclusive = 56,
+        File = 61,
+        Swap = 62,
+        Present = 63,
+    }
+
+    location_mask = (1 << 55) - 1;
+    page_shift_mask = None;
+
+    PRESENT: Self = Present;
+    SWAPPED: Self = Swap;
+    FILE_OR_SHM: Self = File;
+    EXCLUSIVE: Option<Self> = Some(MmapExclusive);
+    SOFT_DIRTY: Option<Self> = Some(SoftDirty);
+}
+
+// pagemap for kernel 5.4.0
+pagemap! {
+    PM5_4_0 {
+        SoftDirty = 55,
+        MmapExclusive = 56,
+        File = 61,
+        Swap = 62,
+        Present = 63,
+    }
+
+    location_mask = (1 << 55) - 1;
+    page_shift_mask = None;
+
+    PRESENT: Self = Present;
+    SWAPPED: Self = Swap;
+    FILE_OR_SHM: Self = File;
+    EXCLUSIVE: Option<Self> = Some(MmapExclusive);
+    SOFT_DIRTY: Option<Self> = Some(SoftDirty);
+}
+
+// pagemap for kernel 5.13.0
+pagemap! {
+    PM5_13_0 {
+        SoftDirty = 55,
+        MmapExclusive = 56,
+        UffdWp = 57,
+        File = 61,
+        Swap = 62,
+        Present = 63,
+    }
+
+    location_mask = (1 << 55) - 1;
+    page_shift_mask = None;
+
+    PRESENT: Self = Present;
+    SWAPPED: Self = Swap;
+    FILE_OR_SHM: Self = File;
+    EXCLUSIVE: Option<Self> = Some(MmapExclusive);
+    SOFT_DIRTY: Option<Self> = Some(SoftDirty);
+}
+
+// pagemap for kernel 5.15.0
+pagemap! {
+    PM5_15_0 {
+        SoftDirty = 55,
+        MmapExclusive = 56,
+        UffdWp = 57,
+        File = 61,
+        Swap = 62,
+        Present = 63,
+    }
+
+    location_mask = (1 << 55) - 1;
+    page_shift_mask = None;
+
+    PRESENT: Self = Present;
+    SWAPPED: Self = Swap;
+    FILE_OR_SHM: Self = File;
+    EXCLUSIVE: Option<Self> = Some(MmapExclusive);
+    SOFT_DIRTY: Option<Self> = Some(SoftDirty);
+}
+
+// pagemap for kernel 5.17.0
+pagemap! {
+    PM5_17_0 {
+        SoftDirty = 55,
+        MmapExclusive = 56,
+        UffdWp = 57,
+        File = 61,
+        Swap = 62,
+        Present = 63,
+    }
+
+    location_mask = (1 << 55) - 1;
+    page_shift_mask = None;
+
+    PRESENT: Self = Present;
+    SWAPPED: Self = Swap;
+    FILE_OR_SHM: Self = File;
+    EXCLUSIVE: Option<Self> = Some(MmapExclusive);
+    SOFT_DIRTY: Option<Self> = Some(SoftDirty);
+}
+
+// pagemap for kernel 5.19.0
+pagemap! {
+    PM5_19_0 {
+        SoftDirty = 55,
+        MmapExclusive = 56,
+        UffdWp = 57,
+        File = 61,
+        Swap = 62,
+        Present = 63,
+    }
+
+    location_mask = (1 << 55) - 1;
+    page_shift_mask = None;
+
+    PRESENT: Self = Present;
+    SWAPPED: Self = Swap;
+    FILE_OR_SHM: Self = File;
+    EXCLUSIVE: Option<Self> = Some(MmapExclusive);
+    SOFT_DIRTY: Option<Self> = Some(SoftDirty);
+}
+
+// pagemap for kernel 6.0.0
+pagemap! {
+    PM6_0_0 {
+        SoftDirty = 55,
+        MmapExclusive = 56,
+        UffdWp = 57,
+        File = 61,
+        Swap = 62,
+        Present = 63,
+    }
+
+    location_mask = (1 << 55) - 1;
+    page_shift_mask = None;
+
+    PRESENT: Self = Present;
+    SWAPPED: Self = Swap;
+    FILE_OR_SHM: Self = File;
+    EXCLUSIVE: Option<Self> = Some(MmapExclusive);
+    SOFT_DIRTY: Option<Self> = Some(SoftDirty);
+}
+
+// pagemap for kernel 6.1.0
+pagemap! {
+    PM6_1_0 {
+        SoftDirty = 55,
+        MmapExclusive = 56,
+        UffdWp = 57,
+        File = 61,
+        Swap = 62,
+        Present = 63,
+    }
+
+    location_mask = (1 << 55) - 1;
+    page_shift_mask = None;
+
+    PRESENT: Self = Present;
+    SWAPPED: Self = Swap;
+    FILE_OR_SHM: Self = File;
+    EXCLUSIVE: Option<Self> = Some(MmapExclusive);
+    SOFT_DIRTY: Option<Self> = Some(SoftDirty);
+}
+
+// pagemap for kernel 6.6.0
+pagemap! {
+    PM6_6_0 {
+        SoftDirty = 55,
+        MmapExclusive = 56,
+        UffdWp = 57,
+        File = 61,
+        Swap = 62,
+        Present = 63,
+    }
+
+    location_mask = (1 << 55) - 1;
+    page_shift_mask = None;
+
+    PRESENT: Self = Present;
+    SWAPPED: Self = Swap;
+    FILE_OR_SHM: Self = File;
+    EXCLUSIVE: Option<Self> = Some(MmapExclusive);
+    SOFT_DIRTY: Option<Self> = Some(SoftDirty);
+}
+
+// pagemap for kernel 6.12.0
+pagemap! {
+    PM6_12_0 {
+        SoftDirty = 55,
+        MmapExclusive = 56,
+        UffdWp = 57,
+        File = 61,
+        Swap = 62,
+        Present = 63,
+    }
+
+    location_mask = (1 << 55) - 1;
+    page_shift_mask = None;
+
+    PRESENT: Self = Present;
+    SWAPPED: Self = Swap;
+    FILE_OR_SHM: Self = File;
+    EXCLUSIVE: Option<Self> = Some(MmapExclusive);
+    SOFT_DIRTY: Option<Self> = Some(SoftDirty);
+}
@@ -0,0 +1,109 @@
+//! Abstractions for reading pagemap, either as a stream or by virtual address.
+
+use std::{fs::File, io, ops::Range};
+
+use crate::{page_size, Error, FileReadableFile, FileReadableReader};
+
+use super::{PageMapPage, PageMappy};
+
+/// Wrapper around a `Read` type that for the `/proc/[pid]/pagemap` file.
+pub type PageMapReader<R, K> = FileReadableReader<R, PageMapPage<K>>;
+
+/// Random-access reader for `/proc/[pid]/pagemap`, addressed by virtual address.
+///
+/// The pagemap file is sparse over the whole virtual address space, so rather than reading it
+/// sequentially, we seek directly to the entries of the pages we care about.
+pub struct PageMapFile<K: PageMappy> {
+    file: FileReadableFile<PageMapPage<K>>,
+    page_size: usize,
+}
+
+impl<K: PageMappy> PageMapFile<K> {
+    pub fn new(file: File) -> Self {
+        PageMapFile {
+            file: FileReadableFile::new(file),
+            page_size: page_size(),
+        }
+    }
+
+    /// Opens `/proc/[pid]/pagemap`.
+    pub fn open(pid: u32) -> io::Result<Self> {
+        Ok(Self::new(File::open(format!("/proc/{pid}/pagemap"))?))
+    }
+
+    /// Opens `/proc/self/pagemap`.
+    pub fn open_self() -> io::Result<Self> {
+        Ok(Self::new(File::open("/proc/self/pagemap")?))
+    }
+
+    /// The size of the pages described by each entry.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Reads the entry for the page containing `vaddr`.
+    pub fn get(&self, vaddr: usize) -> io::Result<PageMapPage<K>> {
+        self.file.get((vaddr / self.page_size) as u64)
+    }
+
+    /// Returns an iterator over the entries for all pages overlapping the given range of virtual
+    /// addresses, paired with the virtual address of each page. Entries are read a bounded chunk
+    /// at a time, so this is fine for huge ranges. The iterator ends early if the range extends
+    /// past the end of the address space.
+    pub fn read_range(
+        &self,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = Result<(usize, PageMapPage<K>), Error>> + '_ {
+        let first = range.start / self.page_size;
+        let last = range.end.div_ceil(self.page_size);
+
+        self.file
+            .iter_range(first as u64..last as u64)
+            .enumerate()
+            .map(move |(i, page)| Ok(((first + i) * self.page_size, page?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::pagemap::PM6_0_0;
+
+    /// Writes a fake pagemap whose entry for each page is the page's index.
+    fn fake_pagemap(npages: u64) -> File {
+        let mut file = tempfile::tempfile().unwrap();
+        let entries: Vec<u8> = (0..npages).flat_map(u64::to_ne_bytes).collect();
+        file.write_all(&entries).unwrap();
+        file
+    }
+
+    #[test]
+    fn read_range_spans_chunks() {
+        let pagemap = PageMapFile::<PM6_0_0::Flags>::new(fake_pagemap(300_000));
+        let page_size = pagemap.page_size();
+
+        // Start and end in the middle of pages, more than one chunk apart.
+        let range = 10 * page_size + 1..290_000 * page_size - 1;
+        let pages: Vec<_> = pagemap
+            .read_range(range)
+            .map(|page| page.map(|(vaddr, page)| (vaddr, page.as_u64())))
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(pages.len(), 290_000 - 10);
+        for (vaddr, entry) in pages {
+            assert_eq!(vaddr as u64, entry * page_size as u64);
+        }
+    }
+
+    #[test]
+    fn read_range_stops_at_eof() {
+        let pagemap = PageMapFile::<PM6_0_0::Flags>::new(fake_pagemap(100));
+        let page_size = pagemap.page_size();
+
+        assert_eq!(pagemap.read_range(90 * page_size..usize::MAX).count(), 10);
+        assert_eq!(pagemap.read_range(200 * page_size..usize::MAX).count(), 0);
+    }
+}
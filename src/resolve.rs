@@ -0,0 +1,207 @@
+//! Tools for resolving virtual pages of a process to the flags of their physical pages, by joining
+//! `/proc/[pid]/pagemap` with `/proc/kpageflags`.
+
+use std::{io, ops::Range};
+
+use crate::{
+    error::Error,
+    kpageflags::{Flaggy, KPageFlags, KPageFlagsFile, KPAGEFLAGS_PATH},
+    pagemap::{PageMapFile, PageMapPage, PageMappy},
+};
+
+/// A resolved page: `(vaddr, pfn, flags)`. See `PageResolver::resolve`.
+pub type ResolvedPage<K> = (usize, u64, Option<KPageFlags<K>>);
+
+/// A resolved page with its whole pagemap entry: `(vaddr, entry, flags)`. See
+/// `PageResolver::resolve_entries`.
+pub type ResolvedEntry<P, K> = (usize, PageMapPage<P>, Option<KPageFlags<K>>);
+
+/// Resolves virtual addresses of a process to the PFNs and flags of the physical pages backing
+/// them, similar to `page-types -p`.
+///
+/// Reading PFNs from pagemap requires `CAP_SYS_ADMIN`; otherwise, the kernel reports all PFNs as
+/// zero.
+pub struct PageResolver<P: PageMappy, K: Flaggy> {
+    pagemap: PageMapFile<P>,
+    kpageflags: KPageFlagsFile<K>,
+}
+
+impl<P: PageMappy, K: Flaggy> PageResolver<P, K> {
+    pub fn new(pagemap: PageMapFile<P>, kpageflags: KPageFlagsFile<K>) -> Self {
+        PageResolver {
+            pagemap,
+            kpageflags,
+        }
+    }
+
+    /// Opens the pagemap of the process with the given pid and `/proc/kpageflags`.
+    pub fn open(pid: u32) -> io::Result<Self> {
+        Ok(Self::new(
+            PageMapFile::open(pid)?,
+            KPageFlagsFile::open(KPAGEFLAGS_PATH)?,
+        ))
+    }
+
+    /// Returns `(vaddr, pfn, flags)` for each present page overlapping the given range of virtual
+    /// addresses. Pages that are not present (e.g., swapped or never touched) are skipped. `flags`
+    /// is `None` for PFNs past the end of `/proc/kpageflags` (e.g., device memory).
+    ///
+    /// Returns `Error::PermissionDenied` if every present page has PFN 0, which is what the kernel
+    /// reports without `CAP_SYS_ADMIN`.
+    pub fn resolve(&self, range: Range<usize>) -> Result<Vec<ResolvedPage<K>>, Error> {
+        Ok(self
+            .resolve_entries(range)?
+            .into_iter()
+            .map(|(vaddr, page, flags)| (vaddr, page.pfn().unwrap(), flags))
+            .collect())
+    }
+
+    /// Like `resolve`, but returns the whole pagemap entry of each page rather than just its PFN,
+    /// e.g., for its `FILE_OR_SHM` and `EXCLUSIVE` bits.
+    pub fn resolve_entries(&self, range: Range<usize>) -> Result<Vec<ResolvedEntry<P, K>>, Error> {
+        let present: Vec<(usize, PageMapPage<P>, u64)> = self
+            .pagemap
+            .read_range(range)
+            .filter_map(|page| match page {
+                Ok((vaddr, page)) => Some(Ok((vaddr, page, page.pfn()?))),
+                Err(err) => Some(Err(err)),
+            })
+            .collect::<Result<_, _>>()?;
+
+        if !present.is_empty() && present.iter().all(|(_, _, pfn)| *pfn == 0) {
+            return Err(Error::PermissionDenied(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "pagemap reports PFN 0 for every page; reading PFNs requires CAP_SYS_ADMIN",
+            )));
+        }
+
+        let mut resolved = Vec::with_capacity(present.len());
+
+        // Look up runs of consecutive PFNs with a single read each.
+        let mut run_start = 0;
+        while run_start < present.len() {
+            let first_pfn = present[run_start].2;
+            let mut run_end = run_start + 1;
+            while run_end < present.len()
+                && present[run_end].2 == first_pfn + (run_end - run_start) as u64
+            {
+                run_end += 1;
+            }
+
+            // This is short if the run extends past the end of kpageflags.
+            let flags = self
+                .kpageflags
+                .read_range(first_pfn..first_pfn + (run_end - run_start) as u64)?;
+
+            resolved.extend(
+                present[run_start..run_end]
+                    .iter()
+                    .zip(flags.into_iter().map(Some).chain(std::iter::repeat(None)))
+                    .map(|(&(vaddr, page, _), flags)| (vaddr, page, flags)),
+            );
+
+            run_start = run_end;
+        }
+
+        Ok(resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Write};
+
+    use super::*;
+    use crate::{kpageflags::KPF6_0_0, pagemap::PM6_0_0};
+
+    const PRESENT: u64 = 1 << 63;
+
+    fn file_of(words: &[u64]) -> File {
+        let mut file = tempfile::tempfile().unwrap();
+        let bytes: Vec<u8> = words.iter().copied().flat_map(u64::to_ne_bytes).collect();
+        file.write_all(&bytes).unwrap();
+        file
+    }
+
+    fn resolver(
+        pagemap: &[u64],
+        kpageflags: &[u64],
+    ) -> PageResolver<PM6_0_0::Flags, KPF6_0_0::Flags> {
+        PageResolver::new(
+            PageMapFile::new(file_of(pagemap)),
+            KPageFlagsFile::new(file_of(kpageflags)),
+        )
+    }
+
+    #[test]
+    fn resolves_present_pages() {
+        let kpageflags: Vec<u64> = (0..8).map(|pfn| 1 << pfn).collect();
+        let resolver = resolver(&[PRESENT | 3, 0, PRESENT | 4, PRESENT | 1], &kpageflags);
+        let page_size = crate::page_size();
+
+        let resolved: Vec<_> = resolver
+            .resolve(0..4 * page_size)
+            .unwrap()
+            .into_iter()
+            .map(|(vaddr, pfn, flags)| (vaddr / page_size, pfn, flags.unwrap().as_u64()))
+            .collect();
+        assert_eq!(resolved, [(0, 3, 1 << 3), (2, 4, 1 << 4), (3, 1, 1 << 1)]);
+    }
+
+    #[test]
+    fn pfns_past_the_end_have_no_flags() {
+        let resolver = resolver(
+            &[PRESENT | 1, PRESENT | 2, PRESENT | 3],
+            &[0, 1 << 5, 1 << 10],
+        );
+        let page_size = crate::page_size();
+
+        let flags: Vec<_> = resolver
+            .resolve(0..3 * page_size)
+            .unwrap()
+            .into_iter()
+            .map(|(_, pfn, flags)| (pfn, flags.map(KPageFlags::as_u64)))
+            .collect();
+        assert_eq!(flags, [(1, Some(1 << 5)), (2, Some(1 << 10)), (3, None)]);
+    }
+
+    #[test]
+    fn entries_are_kept() {
+        let file = u64::from(PM6_0_0::File);
+        let resolver = resolver(
+            &[PRESENT | 1 << file | 2, PRESENT | 3],
+            &[0, 0, 1 << 5, 1 << 10],
+        );
+        let page_size = crate::page_size();
+
+        let entries: Vec<_> = resolver
+            .resolve_entries(0..2 * page_size)
+            .unwrap()
+            .into_iter()
+            .map(|(_, page, flags)| (page.as_u64(), flags.map(KPageFlags::as_u64)))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (PRESENT | 1 << file | 2, Some(1 << 5)),
+                (PRESENT | 3, Some(1 << 10)),
+            ]
+        );
+    }
+
+    #[test]
+    fn all_zero_pfns_are_permission_denied() {
+        let resolver = resolver(&[PRESENT, 0, PRESENT], &[1 << 10]);
+        let page_size = crate::page_size();
+
+        assert!(matches!(
+            resolver.resolve(0..3 * page_size),
+            Err(Error::PermissionDenied(_))
+        ));
+        // No present pages at all is fine, though.
+        assert!(resolver
+            .resolve(page_size..2 * page_size)
+            .unwrap()
+            .is_empty());
+    }
+}
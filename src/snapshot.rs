@@ -0,0 +1,588 @@
+//! A self-describing container for captures of `/proc/kpageflags` and friends.
+//!
+//! A raw copy of `/proc/kpageflags` can only be interpreted with the right `Flaggy`
+//! implementation, and nothing in it says which one that is. A snapshot stores the records along
+//! with a header describing the machine they came from, so that `Snapshot::dispatch` can pick the
+//! matching layout on load.
+//!
+//! The format is:
+//!
+//! - The magic bytes `KPFSNAP\0`.
+//! - The header: the format version (`u32`), a byte-order mark (`u32`, `0x01020304`), the page
+//!   size, the PFN of the first record, and the capture time in seconds since the Unix epoch (all
+//!   `u64`), then the kernel release, architecture, and host name (each a `u32` length followed
+//!   by UTF-8 bytes). The header is padded with zeros to a multiple of 8 bytes.
+//! - Any number of sections until EOF. Each section is a kind (`u32`), 4 reserved bytes, the
+//!   number of records (`u64`), and then the 8-byte records, exactly as read from `/proc`.
+//!
+//! All integers are in the byte order of the machine that wrote the snapshot. Sections start at
+//! 8-byte aligned offsets, so the records can also be mapped into memory directly.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    os::unix::fs::FileExt,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    as_bytes,
+    kernel::{KernelError, KernelLayout, KernelVersion, LayoutVisitor, OSRELEASE_PATH},
+    kpagecgroup::KPageCgroupIterator,
+    kpagecount::KPageCountIterator,
+    kpageflags::{Flaggy, KPageFlagsIterator},
+    Error, FileReadable, FileReadableIterator, FileReadableReader,
+};
+
+/// The magic bytes at the start of every snapshot.
+pub const SNAPSHOT_MAGIC: [u8; 8] = *b"KPFSNAP\0";
+
+/// The version of the format written by `SnapshotWriter`.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Written in native byte order, so that readers can detect snapshots from machines with a
+/// different byte order.
+const BYTE_ORDER_MARK: u32 = 0x0102_0304;
+
+/// The file path... `/proc/sys/kernel/hostname`.
+const HOSTNAME_PATH: &str = "/proc/sys/kernel/hostname";
+
+/// The size of a record in any section.
+const RECORD_SIZE: u64 = 8;
+
+/// The size of the header of a section.
+const SECTION_HEADER_SIZE: u64 = 16;
+
+/// The kinds of sections a snapshot may contain.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+#[repr(u32)]
+pub enum SectionKind {
+    /// Records from `/proc/kpageflags`.
+    KPageFlags = 1,
+    /// Records from `/proc/kpagecount`.
+    KPageCount = 2,
+    /// Records from `/proc/kpagecgroup`.
+    KPageCgroup = 3,
+}
+
+impl SectionKind {
+    fn from_u32(kind: u32) -> Option<Self> {
+        match kind {
+            1 => Some(SectionKind::KPageFlags),
+            2 => Some(SectionKind::KPageCount),
+            3 => Some(SectionKind::KPageCgroup),
+            _ => None,
+        }
+    }
+}
+
+/// Describes the machine a snapshot was captured on.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct SnapshotHeader {
+    /// The kernel release, e.g., `5.15.0-91-generic`.
+    pub release: String,
+    /// The architecture, as in `std::env::consts::ARCH`.
+    pub arch: String,
+    /// The base page size in bytes.
+    pub page_size: u64,
+    /// The host name.
+    pub host: String,
+    /// The capture time in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The PFN of the first record in each section.
+    pub pfn_base: u64,
+}
+
+impl SnapshotHeader {
+    /// Describes the running machine, at the current time, with a `pfn_base` of 0.
+    pub fn current() -> io::Result<Self> {
+        Ok(SnapshotHeader {
+            release: std::fs::read_to_string(OSRELEASE_PATH)?.trim().to_owned(),
+            arch: std::env::consts::ARCH.to_owned(),
+            page_size: crate::page_size() as u64,
+            host: std::fs::read_to_string(HOSTNAME_PATH)?.trim().to_owned(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            pfn_base: 0,
+        })
+    }
+
+    /// Parses the kernel version from `release`.
+    pub fn kernel_version(&self) -> Result<KernelVersion, KernelError> {
+        self.release.parse()
+    }
+
+    fn write_to<W: Write>(&self, mut writer: W) -> io::Result<u64> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&SNAPSHOT_MAGIC);
+        buf.extend_from_slice(&SNAPSHOT_VERSION.to_ne_bytes());
+        buf.extend_from_slice(&BYTE_ORDER_MARK.to_ne_bytes());
+        buf.extend_from_slice(&self.page_size.to_ne_bytes());
+        buf.extend_from_slice(&self.pfn_base.to_ne_bytes());
+        buf.extend_from_slice(&self.timestamp.to_ne_bytes());
+        for s in [&self.release, &self.arch, &self.host] {
+            buf.extend_from_slice(&(s.len() as u32).to_ne_bytes());
+            buf.extend_from_slice(s.as_bytes());
+        }
+        buf.resize(buf.len().next_multiple_of(RECORD_SIZE as usize), 0);
+
+        writer.write_all(&buf)?;
+        Ok(buf.len() as u64)
+    }
+
+    /// Reads the header of a file of `file_len` bytes, returning it along with its size in bytes,
+    /// including padding.
+    fn read_from<R: Read>(mut reader: R, file_len: u64) -> io::Result<(Self, u64)> {
+        fn invalid(reason: &str) -> io::Error {
+            Error::InvalidSnapshot(reason.to_owned()).into()
+        }
+
+        let mut read_bytes = |n: usize| -> io::Result<Vec<u8>> {
+            let mut buf = vec![0; n];
+            reader.read_exact(&mut buf).map_err(|err| {
+                if err.kind() == io::ErrorKind::UnexpectedEof {
+                    invalid("header is truncated")
+                } else {
+                    err
+                }
+            })?;
+            Ok(buf)
+        };
+
+        if read_bytes(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err(invalid("bad magic; not a snapshot"));
+        }
+
+        let mut read_u32 =
+            || -> io::Result<u32> { Ok(u32::from_ne_bytes(read_bytes(4)?.try_into().unwrap())) };
+        let version = read_u32()?;
+        let bom = read_u32()?;
+        if bom == BYTE_ORDER_MARK.swap_bytes() {
+            return Err(invalid("written by a machine with a different byte order"));
+        } else if bom != BYTE_ORDER_MARK {
+            return Err(invalid("bad byte-order mark"));
+        } else if version != SNAPSHOT_VERSION {
+            return Err(Error::InvalidSnapshot(format!("unsupported version {}", version)).into());
+        }
+
+        let mut read_u64 =
+            || -> io::Result<u64> { Ok(u64::from_ne_bytes(read_bytes(8)?.try_into().unwrap())) };
+        let page_size = read_u64()?;
+        let pfn_base = read_u64()?;
+        let timestamp = read_u64()?;
+
+        // Don't trust the lengths of the strings until we know the file is that long.
+        let mut consumed = 40;
+        let mut read_string = || -> io::Result<String> {
+            let len = u32::from_ne_bytes(read_bytes(4)?.try_into().unwrap()) as u64;
+            consumed += 4;
+            if len > file_len.saturating_sub(consumed) {
+                return Err(invalid("header string is longer than the file"));
+            }
+            consumed += len;
+
+            String::from_utf8(read_bytes(len as usize)?)
+                .map_err(|_| invalid("header string is not UTF-8"))
+        };
+        let release = read_string()?;
+        let arch = read_string()?;
+        let host = read_string()?;
+
+        // The fixed-size fields, then the strings and their lengths.
+        let len = 40 + 12 + (release.len() + arch.len() + host.len()) as u64;
+        read_bytes((len.next_multiple_of(RECORD_SIZE) - len) as usize)?;
+
+        Ok((
+            SnapshotHeader {
+                release,
+                arch,
+                page_size,
+                host,
+                timestamp,
+                pfn_base,
+            },
+            len.next_multiple_of(RECORD_SIZE),
+        ))
+    }
+}
+
+/// Writes a snapshot: the header, followed by sections added one at a time.
+///
+/// The writer has to be seekable, so that the length of a section can be filled in after its
+/// records have been copied from a stream of unknown length.
+pub struct SnapshotWriter<W: Write + Seek> {
+    writer: W,
+}
+
+impl<W: Write + Seek> SnapshotWriter<W> {
+    /// Writes the header to `writer`.
+    pub fn new(mut writer: W, header: &SnapshotHeader) -> io::Result<Self> {
+        header.write_to(&mut writer)?;
+        Ok(SnapshotWriter { writer })
+    }
+
+    /// Writes a section containing the given records.
+    pub fn write_records<T: FileReadable>(
+        &mut self,
+        kind: SectionKind,
+        records: &[T],
+    ) -> io::Result<()> {
+        assert_eq!(std::mem::size_of::<T>() as u64, RECORD_SIZE);
+
+        self.write_section_header(kind, records.len() as u64)?;
+        self.writer.write_all(as_bytes(records))
+    }
+
+    /// Writes a section containing all records read from `reader`, e.g., `/proc/kpageflags`
+    /// itself. Returns the number of records copied.
+    pub fn copy_section<R: Read>(&mut self, kind: SectionKind, mut reader: R) -> io::Result<u64> {
+        let start = self.writer.stream_position()?;
+        self.write_section_header(kind, 0)?;
+
+        // `/proc/kpageflags` and friends only allow reads of whole records, so keep the buffer a
+        // multiple of the record size.
+        let mut buf = vec![0u8; 1 << 20];
+        let mut total_bytes = 0;
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.writer.write_all(&buf[..n])?;
+                    total_bytes += n as u64;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        let trailing_bytes = (total_bytes % RECORD_SIZE) as usize;
+        if trailing_bytes != 0 {
+            return Err(Error::Truncated { trailing_bytes }.into());
+        }
+
+        // Go back and fill in the number of records.
+        let nrecords = total_bytes / RECORD_SIZE;
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(start))?;
+        self.write_section_header(kind, nrecords)?;
+        self.writer.seek(SeekFrom::Start(end))?;
+
+        Ok(nrecords)
+    }
+
+    /// Flushes the snapshot and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_section_header(&mut self, kind: SectionKind, nrecords: u64) -> io::Result<()> {
+        self.writer.write_all(&(kind as u32).to_ne_bytes())?;
+        self.writer.write_all(&[0; 4])?;
+        self.writer.write_all(&nrecords.to_ne_bytes())
+    }
+}
+
+/// The location of a section in a snapshot file.
+#[derive(Copy, Clone, Debug)]
+struct Section {
+    kind: SectionKind,
+    /// The byte offset of the first record.
+    offset: u64,
+    nrecords: u64,
+}
+
+/// A snapshot opened for reading.
+pub struct Snapshot {
+    file: File,
+    header: SnapshotHeader,
+    sections: Vec<Section>,
+}
+
+impl Snapshot {
+    /// Reads the header and finds the sections of the snapshot. Sections of unknown kinds are
+    /// skipped.
+    pub fn new(file: File) -> io::Result<Self> {
+        let file_len = file.metadata()?.len();
+        let (header, mut offset) = SnapshotHeader::read_from(BufReader::new(&file), file_len)?;
+
+        let mut sections = Vec::new();
+        while offset < file_len {
+            let mut buf = [0u8; SECTION_HEADER_SIZE as usize];
+            file.read_exact_at(&mut buf, offset).map_err(|err| {
+                if err.kind() == io::ErrorKind::UnexpectedEof {
+                    Error::InvalidSnapshot("section header is truncated".into()).into()
+                } else {
+                    err
+                }
+            })?;
+
+            let kind = u32::from_ne_bytes(buf[..4].try_into().unwrap());
+            let nrecords = u64::from_ne_bytes(buf[8..].try_into().unwrap());
+            let start = offset + SECTION_HEADER_SIZE;
+            offset = nrecords
+                .checked_mul(RECORD_SIZE)
+                .and_then(|len| start.checked_add(len))
+                .ok_or_else(|| {
+                    Error::InvalidSnapshot(format!("section has too many records: {}", nrecords))
+                })?;
+
+            if offset > file_len {
+                return Err(Error::Truncated {
+                    trailing_bytes: ((file_len - start) % RECORD_SIZE) as usize,
+                }
+                .into());
+            }
+
+            if let Some(kind) = SectionKind::from_u32(kind) {
+                sections.push(Section {
+                    kind,
+                    offset: start,
+                    nrecords,
+                });
+            }
+        }
+
+        Ok(Snapshot {
+            file,
+            header,
+            sections,
+        })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(File::open(path)?)
+    }
+
+    pub fn header(&self) -> &SnapshotHeader {
+        &self.header
+    }
+
+    /// Returns the number of records in the first section of the given kind, if any.
+    pub fn len(&self, kind: SectionKind) -> Option<u64> {
+        self.section(kind).map(|section| section.nrecords)
+    }
+
+    /// Picks the layout matching the kernel release of the snapshot.
+    pub fn layout(&self) -> Result<KernelLayout, KernelError> {
+        KernelLayout::for_version(self.header.kernel_version()?)
+    }
+
+    /// Runs `visitor` with the layout matching the kernel release of the snapshot. The visitor
+    /// would typically hold a reference to the snapshot and call `kpageflags`.
+    pub fn dispatch<V: LayoutVisitor>(&self, visitor: V) -> Result<V::Output, KernelError> {
+        Ok(self.layout()?.dispatch(visitor))
+    }
+
+    /// Returns a reader over the records of the first section of the given kind, if any.
+    pub fn section_reader(&self, kind: SectionKind) -> Option<SectionReader<'_>> {
+        self.section(kind).map(|section| SectionReader {
+            file: &self.file,
+            offset: section.offset,
+            end: section.offset + section.nrecords * RECORD_SIZE,
+        })
+    }
+
+    /// Maps the records of the first section of the given kind into memory, if there is one.
+    /// Requires the `mmap` feature.
+    #[cfg(feature = "mmap")]
+    pub fn mmap_section<T: FileReadable>(
+        &self,
+        kind: SectionKind,
+    ) -> io::Result<Option<crate::mmap::FileReadableMmap<T>>> {
+        self.section(kind)
+            .map(|section| {
+                let len = section.nrecords.checked_mul(RECORD_SIZE).ok_or_else(|| {
+                    Error::InvalidSnapshot(format!(
+                        "section has too many records: {}",
+                        section.nrecords
+                    ))
+                })?;
+                crate::mmap::FileReadableMmap::with_range(&self.file, section.offset, len)
+            })
+            .transpose()
+    }
+
+    /// Iterates over the `/proc/kpageflags` section, starting at PFN `header().pfn_base`.
+    pub fn kpageflags<K: Flaggy>(
+        &self,
+        ignored_flags: &[K],
+    ) -> Option<KPageFlagsIterator<SectionReader<'_>, K>> {
+        self.section_reader(SectionKind::KPageFlags).map(|reader| {
+            KPageFlagsIterator::new(
+                FileReadableReader::new(BufReader::new(reader)),
+                ignored_flags,
+            )
+        })
+    }
+
+    /// Iterates over the `/proc/kpagecount` section, starting at PFN `header().pfn_base`.
+    pub fn kpagecount(&self) -> Option<KPageCountIterator<SectionReader<'_>>> {
+        self.section_reader(SectionKind::KPageCount).map(|reader| {
+            FileReadableIterator::new(FileReadableReader::new(BufReader::new(reader)))
+        })
+    }
+
+    /// Iterates over the `/proc/kpagecgroup` section, starting at PFN `header().pfn_base`.
+    pub fn kpagecgroup(&self) -> Option<KPageCgroupIterator<SectionReader<'_>>> {
+        self.section_reader(SectionKind::KPageCgroup).map(|reader| {
+            FileReadableIterator::new(FileReadableReader::new(BufReader::new(reader)))
+        })
+    }
+
+    fn section(&self, kind: SectionKind) -> Option<&Section> {
+        self.sections.iter().find(|section| section.kind == kind)
+    }
+}
+
+/// Reads the records of one section of a `Snapshot`. Uses positioned reads, so any number of
+/// these can be used at the same time.
+pub struct SectionReader<'s> {
+    file: &'s File,
+    /// The byte offset of the next read.
+    offset: u64,
+    /// The byte offset of the end of the section.
+    end: u64,
+}
+
+impl Read for SectionReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min((self.end - self.offset) as usize);
+        let n = self.file.read_at(&mut buf[..len], self.offset)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+/// Captures `/proc/kpageflags` into a snapshot, along with `/proc/kpagecount` and
+/// `/proc/kpagecgroup` if requested.
+pub fn capture<W: Write + Seek>(writer: W, counts: bool, cgroups: bool) -> io::Result<W> {
+    let mut snapshot = SnapshotWriter::new(writer, &SnapshotHeader::current()?)?;
+
+    snapshot.copy_section(
+        SectionKind::KPageFlags,
+        File::open(crate::kpageflags::KPAGEFLAGS_PATH)?,
+    )?;
+    if counts {
+        snapshot.copy_section(
+            SectionKind::KPageCount,
+            File::open(crate::kpagecount::KPAGECOUNT_PATH)?,
+        )?;
+    }
+    if cgroups {
+        snapshot.copy_section(
+            SectionKind::KPageCgroup,
+            File::open(crate::kpagecgroup::KPAGECGROUP_PATH)?,
+        )?;
+    }
+
+    snapshot.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::kpageflags::KPF6_0_0;
+
+    fn header() -> SnapshotHeader {
+        SnapshotHeader {
+            release: "6.0.0-test".into(),
+            arch: "x86_64".into(),
+            page_size: 4096,
+            host: "host".into(),
+            timestamp: 1,
+            pfn_base: 0x100,
+        }
+    }
+
+    /// Writes a snapshot with a kpageflags section of `nrecords` records and returns its bytes,
+    /// along with the byte offset of the section header.
+    fn snapshot_bytes(nrecords: u64) -> (Vec<u8>, usize) {
+        let records: Vec<u8> = (0..nrecords)
+            .flat_map(|i| (1u64 << (i % 8)).to_ne_bytes())
+            .collect();
+        let mut writer = SnapshotWriter::new(Cursor::new(Vec::new()), &header()).unwrap();
+        let section = writer.writer.position() as usize;
+        writer
+            .copy_section(SectionKind::KPageFlags, &records[..])
+            .unwrap();
+        (writer.finish().unwrap().into_inner(), section)
+    }
+
+    fn open(bytes: &[u8]) -> io::Result<Snapshot> {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(bytes).unwrap();
+        file.rewind().unwrap();
+        Snapshot::new(file)
+    }
+
+    fn invalid_snapshot(result: io::Result<Snapshot>) -> bool {
+        matches!(result.map_err(Error::from), Err(Error::InvalidSnapshot(_)))
+    }
+
+    #[test]
+    fn round_trip() {
+        let (bytes, _) = snapshot_bytes(10);
+        let snapshot = open(&bytes).unwrap();
+
+        assert_eq!(*snapshot.header(), header());
+        assert_eq!(snapshot.len(SectionKind::KPageFlags), Some(10));
+        let flags: Vec<_> = snapshot
+            .kpageflags::<KPF6_0_0::Flags>(&[])
+            .unwrap()
+            .map(|flags| flags.unwrap().as_u64())
+            .collect();
+        assert_eq!(flags, (0..10).map(|i| 1 << (i % 8)).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn huge_section_is_invalid() {
+        let (mut bytes, section) = snapshot_bytes(10);
+        for nrecords in [u64::MAX, u64::MAX / RECORD_SIZE] {
+            bytes[section + 8..section + 16].copy_from_slice(&nrecords.to_ne_bytes());
+            assert!(invalid_snapshot(open(&bytes)));
+        }
+
+        // Merely too long for the file is truncated.
+        bytes[section + 8..section + 16].copy_from_slice(&11u64.to_ne_bytes());
+        assert!(matches!(
+            open(&bytes).map_err(Error::from),
+            Err(Error::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn huge_header_string_is_invalid() {
+        let (mut bytes, _) = snapshot_bytes(10);
+        bytes[40..44].copy_from_slice(&u32::MAX.to_ne_bytes());
+        assert!(invalid_snapshot(open(&bytes)));
+
+        let len = bytes.len() as u32;
+        bytes[40..44].copy_from_slice(&len.to_ne_bytes());
+        assert!(invalid_snapshot(open(&bytes)));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap_section() {
+        use crate::kpageflags::KPageFlags;
+
+        let (bytes, _) = snapshot_bytes(10);
+        let snapshot = open(&bytes).unwrap();
+
+        let flags = snapshot
+            .mmap_section::<KPageFlags<KPF6_0_0::Flags>>(SectionKind::KPageFlags)
+            .unwrap()
+            .unwrap();
+        assert_eq!(flags.len(), 10);
+        assert_eq!(flags[9].as_u64(), 1 << 1);
+        assert!(snapshot
+            .mmap_section::<KPageFlags<KPF6_0_0::Flags>>(SectionKind::KPageCount)
+            .unwrap()
+            .is_none());
+    }
+}
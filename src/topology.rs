@@ -0,0 +1,522 @@
+//! Tools for attributing PFNs to NUMA nodes and memory zones, via
+//! `/sys/devices/system/node/node*/` and `/proc/zoneinfo`.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    ops::Range,
+    path::Path,
+};
+
+use crate::kpageflags::{FlagHistogram, Flaggy, KPageFlags, KPageFlagsRegions, Region};
+
+/// The directory... `/sys/devices/system/node`.
+pub const NODE_ROOT: &str = "/sys/devices/system/node";
+
+/// The file path... `/sys/devices/system/memory/block_size_bytes`.
+pub const MEMORY_BLOCK_SIZE_PATH: &str = "/sys/devices/system/memory/block_size_bytes";
+
+/// The file path... `/proc/zoneinfo`.
+pub const ZONEINFO_PATH: &str = "/proc/zoneinfo";
+
+fn invalid_data(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// Returns the size of a memory block (the unit of memory hotplug) in bytes.
+pub fn memory_block_size() -> io::Result<u64> {
+    // The kernel prints it in hex, without a `0x` prefix.
+    let size = std::fs::read_to_string(MEMORY_BLOCK_SIZE_PATH)?;
+    u64::from_str_radix(size.trim(), 16).map_err(invalid_data)
+}
+
+/// Returns the memory blocks of each NUMA node, by block number, from the `memoryN` links in each
+/// `nodeN` directory under `root`, e.g., `NODE_ROOT`.
+pub fn read_node_memory_blocks<P: AsRef<Path>>(root: P) -> io::Result<BTreeMap<u32, Vec<u64>>> {
+    let mut nodes = BTreeMap::new();
+
+    for entry in std::fs::read_dir(root)? {
+        let entry = entry?;
+        let Some(node) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix("node"))
+            .and_then(|node| node.parse().ok())
+        else {
+            continue;
+        };
+
+        let mut blocks = Vec::new();
+        for entry in std::fs::read_dir(entry.path())? {
+            if let Some(block) = entry?
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("memory"))
+                .and_then(|block| block.parse().ok())
+            {
+                blocks.push(block);
+            }
+        }
+        blocks.sort_unstable();
+
+        nodes.insert(node, blocks);
+    }
+
+    Ok(nodes)
+}
+
+/// A memory zone of a NUMA node, from `/proc/zoneinfo`.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Zone {
+    pub node: u32,
+    /// E.g., `DMA32` or `Normal`.
+    pub name: String,
+    pub start_pfn: u64,
+    /// The number of PFNs in the zone, including holes.
+    pub spanned: u64,
+    /// The number of pages that actually exist in the zone.
+    pub present: u64,
+}
+
+impl Zone {
+    /// Returns the range of PFNs spanned by the zone.
+    pub fn pfns(&self) -> Range<u64> {
+        self.start_pfn..self.start_pfn + self.spanned
+    }
+}
+
+/// Parses the contents of `/proc/zoneinfo`. Empty zones have no `start_pfn`, so they get 0.
+pub fn parse_zoneinfo<R: BufRead>(reader: R) -> io::Result<Vec<Zone>> {
+    let mut zones: Vec<Zone> = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+
+        // E.g., `Node 0, zone   Normal`
+        if let Some(rest) = line.strip_prefix("Node ") {
+            let (node, name) = rest
+                .split_once(", zone")
+                .ok_or_else(|| invalid_data(format!("bad zone header: {line}")))?;
+            zones.push(Zone {
+                node: node.parse().map_err(invalid_data)?,
+                name: name.trim().to_owned(),
+                start_pfn: 0,
+                spanned: 0,
+                present: 0,
+            });
+            continue;
+        }
+
+        let Some(zone) = zones.last_mut() else {
+            continue;
+        };
+        let mut fields = line.split_whitespace();
+        let field = match fields.next() {
+            Some("spanned") => &mut zone.spanned,
+            Some("present") => &mut zone.present,
+            Some("start_pfn:") => &mut zone.start_pfn,
+            _ => continue,
+        };
+        *field = fields
+            .next()
+            .ok_or_else(|| invalid_data(format!("missing value: {line}")))?
+            .parse()
+            .map_err(invalid_data)?;
+    }
+
+    Ok(zones)
+}
+
+/// Reads and parses `/proc/zoneinfo`.
+pub fn read_zoneinfo() -> io::Result<Vec<Zone>> {
+    parse_zoneinfo(BufReader::new(File::open(ZONEINFO_PATH)?))
+}
+
+/// A range of PFNs in a single node and zone.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct PfnSpan {
+    pub pfns: Range<u64>,
+    pub node: u32,
+    pub zone: String,
+}
+
+/// Makes the spans of a single node disjoint, giving each PFN to the innermost span containing
+/// it, i.e., the one that starts last. Outer spans are split around inner ones.
+fn split_nested(spans: Vec<PfnSpan>) -> Vec<PfnSpan> {
+    let mut bounds: Vec<u64> = spans
+        .iter()
+        .flat_map(|span| [span.pfns.start, span.pfns.end])
+        .collect();
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut disjoint: Vec<PfnSpan> = Vec::new();
+    for pfns in bounds.windows(2).map(|bounds| bounds[0]..bounds[1]) {
+        let Some(span) = spans
+            .iter()
+            .filter(|span| span.pfns.start <= pfns.start && pfns.end <= span.pfns.end)
+            .max_by_key(|span| (span.pfns.start, std::cmp::Reverse(span.pfns.end)))
+        else {
+            continue;
+        };
+
+        match disjoint.last_mut() {
+            Some(last) if last.pfns.end == pfns.start && last.zone == span.zone => {
+                last.pfns.end = pfns.end
+            }
+            _ => disjoint.push(PfnSpan {
+                pfns,
+                node: span.node,
+                zone: span.zone.clone(),
+            }),
+        }
+    }
+
+    disjoint
+}
+
+/// Maps PFNs to the NUMA node and zone they belong to.
+pub struct PfnTopology {
+    /// Disjoint spans, sorted by PFN.
+    spans: Vec<PfnSpan>,
+}
+
+impl PfnTopology {
+    /// Builds the topology from the memory blocks of each node (see `read_node_memory_blocks`)
+    /// and the zones of each node. If `node_blocks` is empty, e.g., because the kernel was built
+    /// without memory hotplug, nodes are attributed by zone only.
+    pub fn from_parts(
+        node_blocks: &BTreeMap<u32, Vec<u64>>,
+        block_pages: u64,
+        zones: &[Zone],
+    ) -> Self {
+        // Coalesce the blocks of each node into ranges of PFNs.
+        let mut node_ranges: BTreeMap<u32, Vec<Range<u64>>> = BTreeMap::new();
+        for (node, blocks) in node_blocks.iter() {
+            let ranges = node_ranges.entry(*node).or_default();
+            for block in blocks.iter() {
+                let pfns = block * block_pages..(block + 1) * block_pages;
+                match ranges.last_mut() {
+                    Some(last) if last.end == pfns.start => last.end = pfns.end,
+                    _ => ranges.push(pfns),
+                }
+            }
+        }
+
+        let mut node_spans: BTreeMap<u32, Vec<PfnSpan>> = BTreeMap::new();
+        for zone in zones.iter().filter(|zone| zone.spanned > 0) {
+            let zone_pfns = zone.pfns();
+            let spans = node_spans.entry(zone.node).or_default();
+            let mut add = |pfns: Range<u64>| {
+                spans.push(PfnSpan {
+                    pfns,
+                    node: zone.node,
+                    zone: zone.name.clone(),
+                })
+            };
+
+            // Zones of different nodes may overlap, so only keep the parts in the zone's node.
+            match node_ranges.get(&zone.node) {
+                None if node_ranges.is_empty() => add(zone_pfns),
+                None => {}
+                Some(ranges) => {
+                    for range in ranges.iter() {
+                        let start = range.start.max(zone_pfns.start);
+                        let end = range.end.min(zone_pfns.end);
+                        if start < end {
+                            add(start..end);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Within a node, a zone may span another one, e.g., `Movable` inside `Normal`.
+        let mut spans: Vec<_> = node_spans.into_values().flat_map(split_nested).collect();
+        spans.sort_by_key(|span| span.pfns.start);
+
+        // Without memory blocks, zones of different nodes may still overlap. Clip each span to
+        // start after the previous ones, so that the spans are disjoint.
+        let mut end = 0;
+        spans.retain_mut(|span| {
+            span.pfns.start = span.pfns.start.max(end);
+            end = end.max(span.pfns.end);
+            !span.pfns.is_empty()
+        });
+
+        PfnTopology { spans }
+    }
+
+    /// Reads the topology of this machine.
+    pub fn new() -> io::Result<Self> {
+        let node_blocks = match read_node_memory_blocks(NODE_ROOT) {
+            Ok(node_blocks) => node_blocks,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err),
+        };
+        let block_pages = if node_blocks.is_empty() {
+            1
+        } else {
+            memory_block_size()? / crate::page_size() as u64
+        };
+
+        Ok(Self::from_parts(
+            &node_blocks,
+            block_pages,
+            &read_zoneinfo()?,
+        ))
+    }
+
+    /// Returns the spans of PFNs in each node and zone, sorted by PFN.
+    pub fn spans(&self) -> &[PfnSpan] {
+        &self.spans
+    }
+
+    /// Returns the span containing the given PFN, if any.
+    pub fn lookup(&self, pfn: u64) -> Option<&PfnSpan> {
+        let idx = self.spans.partition_point(|span| span.pfns.end <= pfn);
+        self.spans.get(idx).filter(|span| span.pfns.contains(&pfn))
+    }
+
+    /// Returns the node and zone of the given PFN, if known.
+    pub fn node_zone(&self, pfn: u64) -> Option<(u32, &str)> {
+        self.lookup(pfn).map(|span| (span.node, span.zone.as_str()))
+    }
+
+    /// Splits the flags of consecutive pages starting at PFN `start_pfn` by span, calling `f` with
+    /// each span and the flags of the pages in it. Pages outside of any span are skipped.
+    fn for_each_span<'s, K: Flaggy>(
+        &'s self,
+        flags: impl IntoIterator<Item = KPageFlags<K>>,
+        start_pfn: u64,
+        mut f: impl FnMut(&'s PfnSpan, u64, &mut dyn Iterator<Item = KPageFlags<K>>),
+    ) {
+        let mut flags = flags.into_iter();
+        let mut pfn = start_pfn;
+
+        for span in self.spans.iter() {
+            if span.pfns.end <= pfn {
+                continue;
+            }
+            if span.pfns.start > pfn {
+                flags
+                    .by_ref()
+                    .take((span.pfns.start - pfn) as usize)
+                    .for_each(drop);
+                pfn = span.pfns.start;
+            }
+
+            let npages = (span.pfns.end - pfn) as usize;
+            f(span, pfn, &mut flags.by_ref().take(npages));
+            pfn = span.pfns.end;
+        }
+    }
+
+    /// Counts pages per combination of flags, grouped by node and zone. `flags` are the flags of
+    /// consecutive pages starting at PFN `start_pfn`, e.g., from a `KPageFlagsIterator`.
+    pub fn histograms<K: Flaggy>(
+        &self,
+        flags: impl IntoIterator<Item = KPageFlags<K>>,
+        start_pfn: u64,
+    ) -> BTreeMap<(u32, &str), FlagHistogram<K>> {
+        let mut histograms: BTreeMap<_, FlagHistogram<K>> = BTreeMap::new();
+        self.for_each_span(flags, start_pfn, |span, _, flags| {
+            histograms
+                .entry((span.node, span.zone.as_str()))
+                .or_default()
+                .extend(flags);
+        });
+        histograms
+    }
+
+    /// Coalesces pages into regions like `KPageFlagsRegions::new`, but never across a node or
+    /// zone boundary. `flags` are the flags of consecutive pages starting at PFN `start_pfn`.
+    pub fn regions<K: Flaggy>(
+        &self,
+        flags: impl IntoIterator<Item = KPageFlags<K>>,
+        start_pfn: u64,
+    ) -> Vec<(&PfnSpan, Region<K>)> {
+        let mut regions = Vec::new();
+        self.for_each_span(flags, start_pfn, |span, pfn, flags| {
+            regions.extend(KPageFlagsRegions::new(flags, pfn).map(|region| (span, region)));
+        });
+        regions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kpageflags::KPF6_0_0;
+
+    type Flags = KPF6_0_0::Flags;
+
+    fn zone(node: u32, name: &str, pfns: Range<u64>) -> Zone {
+        Zone {
+            node,
+            name: name.to_owned(),
+            start_pfn: pfns.start,
+            spanned: pfns.end - pfns.start,
+            present: pfns.end - pfns.start,
+        }
+    }
+
+    fn pages(n: u64) -> impl Iterator<Item = KPageFlags<Flags>> {
+        (0..n).map(|_| KPageFlags::from(Flags::from(0)))
+    }
+
+    #[test]
+    fn overlapping_zones_are_clipped() {
+        let zones = [
+            zone(0, "Normal", 0..100),
+            zone(1, "Normal", 50..200),
+            zone(1, "Movable", 60..80),
+        ];
+        let topology = PfnTopology::from_parts(&BTreeMap::new(), 1, &zones);
+
+        let spans: Vec<_> = topology
+            .spans()
+            .iter()
+            .map(|span| (span.pfns.clone(), span.node))
+            .collect();
+        assert_eq!(spans, [(0..100, 0), (100..200, 1)]);
+        assert_eq!(topology.node_zone(99), Some((0, "Normal")));
+        assert_eq!(topology.node_zone(100), Some((1, "Normal")));
+        assert_eq!(topology.node_zone(200), None);
+
+        let histograms = topology.histograms(pages(250), 0);
+        let counts: Vec<_> = histograms
+            .iter()
+            .map(|(key, histogram)| (*key, histogram.iter().map(|(_, n)| n).sum::<u64>()))
+            .collect();
+        assert_eq!(counts, [((0, "Normal"), 100), ((1, "Normal"), 100)]);
+    }
+
+    #[test]
+    fn nested_zones_split_the_outer_zone() {
+        let zones = [
+            zone(0, "Normal", 0..100),
+            zone(0, "Movable", 40..60),
+            zone(1, "Normal", 100..200),
+            zone(1, "Movable", 150..200),
+        ];
+        let expected = [
+            (0..40, 0, "Normal"),
+            (40..60, 0, "Movable"),
+            (60..100, 0, "Normal"),
+            (100..150, 1, "Normal"),
+            (150..200, 1, "Movable"),
+        ];
+
+        // With and without memory blocks.
+        let node_blocks = BTreeMap::from([(0, vec![0, 1, 2, 3, 4]), (1, vec![5, 6, 7, 8, 9])]);
+        for (node_blocks, block_pages) in [(BTreeMap::new(), 1), (node_blocks, 20)] {
+            let topology = PfnTopology::from_parts(&node_blocks, block_pages, &zones);
+            let spans: Vec<_> = topology
+                .spans()
+                .iter()
+                .map(|span| (span.pfns.clone(), span.node, span.zone.as_str()))
+                .collect();
+            assert_eq!(spans, expected);
+        }
+    }
+
+    #[test]
+    fn zoneinfo_is_parsed() {
+        // Trimmed from a real `/proc/zoneinfo`.
+        let zoneinfo = "\
+Node 0, zone      DMA
+  per-node stats
+      nr_inactive_anon 161034
+      nr_active_anon 1210512
+  pages free     3840
+        boost    0
+        min      11
+        low      14
+        high     17
+        spanned  4095
+        present  3998
+        managed  3840
+        cma      0
+        protection: (0, 2951, 31835, 31835, 31835)
+      nr_free_pages 3840
+  pagesets
+    cpu: 0
+              count: 0
+              high:  0
+              batch: 1
+  vm stats threshold: 10
+  node_unreclaimable:  0
+  start_pfn:           1
+Node 0, zone    DMA32
+  pages free     742312
+        spanned  1044480
+        present  782288
+        managed  757636
+  start_pfn:           4096
+Node 0, zone   Normal
+  pages free     5531981
+        spanned  7602176
+        present  7602176
+        managed  7395032
+  start_pfn:           1048576
+Node 0, zone  Movable
+  pages free     0
+        boost    0
+        spanned  0
+        present  0
+        managed  0
+        protection: (0, 0, 0, 0, 0)
+";
+        let zones = parse_zoneinfo(zoneinfo.as_bytes()).unwrap();
+        let zone = |name: &str, start_pfn, spanned, present| Zone {
+            node: 0,
+            name: name.to_owned(),
+            start_pfn,
+            spanned,
+            present,
+        };
+        assert_eq!(
+            zones,
+            [
+                zone("DMA", 1, 4095, 3998),
+                zone("DMA32", 4096, 1044480, 782288),
+                zone("Normal", 1048576, 7602176, 7602176),
+                zone("Movable", 0, 0, 0),
+            ]
+        );
+
+        assert!(parse_zoneinfo("Node x, zone Normal\n".as_bytes()).is_err());
+        assert!(parse_zoneinfo("Node 0, zone Normal\n  spanned\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn zones_are_split_by_node_blocks() {
+        let zones = [zone(0, "Normal", 0..40), zone(1, "Normal", 0..40)];
+        let node_blocks = BTreeMap::from([(0, vec![0, 2]), (1, vec![1, 3])]);
+        let topology = PfnTopology::from_parts(&node_blocks, 10, &zones);
+
+        let nodes: Vec<_> = (0..40)
+            .step_by(10)
+            .map(|pfn| topology.node_zone(pfn))
+            .collect();
+        assert_eq!(
+            nodes,
+            [
+                Some((0, "Normal")),
+                Some((1, "Normal")),
+                Some((0, "Normal")),
+                Some((1, "Normal")),
+            ]
+        );
+
+        let regions = topology.regions(pages(35), 5);
+        let regions: Vec<_> = regions
+            .iter()
+            .map(|(span, region)| (span.node, region.start_pfn, region.npages))
+            .collect();
+        assert_eq!(regions, [(0, 5, 5), (1, 10, 10), (0, 20, 10), (1, 30, 10)]);
+    }
+}